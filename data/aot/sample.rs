@@ -0,0 +1,284 @@
+// Generated by the Intcode ahead-of-time translator. Do not edit.
+#![allow(dead_code, clippy::all)]
+
+use std::collections::VecDeque;
+use crate::intcode::error::IntCodeError;
+use crate::intcode::{IntCodeResult, State};
+
+pub const PROGRAM: [i64; 83] = [109, 81, 3, 77, 3, 78, 21001, 78, 0, 1, 21101, 17, 0, 0, 1105, 1, 66, 204, 1, 1001, 78, 1, 24, 104, 0, 1008, 77, 1, 79, 1005, 79, 56, 1008, 77, 2, 79, 1005, 79, 61, 1007, 78, 0, 79, 1006, 79, 47, 99, 1002, 78, 2, 78, 4, 78, 1105, 1, 47, 9, 80, 109, 1, 99, 9, 80, 204, 1, 99, 109, 2, 22202, -1, -1, -1, 109, -2, 2105, 1, 0, 0, 0, 0, 9223372036854775807, 0, 0];
+
+#[derive(Debug)]
+pub struct Machine {
+    pub inputs: VecDeque<i64>,
+    mem: Vec<i64>,
+    pc: usize,
+    rb: i64,
+    fallback: Option<State>,
+}
+
+impl Default for Machine {
+    fn default() -> Self {
+        Machine::new()
+    }
+}
+
+impl Machine {
+    pub fn new() -> Self {
+        Machine {
+            inputs: VecDeque::new(),
+            mem: PROGRAM.to_vec(),
+            pc: 0,
+            rb: 0,
+            fallback: None,
+        }
+    }
+
+    /// Whether execution has been handed over to the interpreter.
+    pub fn is_interpreted(&self) -> bool {
+        self.fallback.is_some()
+    }
+
+    /// Run until the program halts, produces an output or waits for input.
+    pub fn run(&mut self, outputs: &mut Vec<i64>) -> Result<IntCodeResult, IntCodeError> {
+        if let Some(state) = self.fallback.as_mut() {
+            state.inputs.append(&mut self.inputs);
+            return state.run(outputs);
+        }
+
+        loop {
+            match self.pc {
+                0 => {
+                    // ARB #81
+                    self.rb = match self.rb.checked_add(81) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 2;
+                }
+                2 => {
+                    // IN [77]
+                    let v = match self.inputs.pop_front() { Some(v) => v, None => return Ok(IntCodeResult::Input) };
+                    self.write(77, v);
+                    self.pc = 4;
+                }
+                4 => {
+                    // IN [78]
+                    let v = match self.inputs.pop_front() { Some(v) => v, None => return Ok(IntCodeResult::Input) };
+                    self.write(78, v);
+                    self.pc = 6;
+                }
+                6 => {
+                    // ADD [78], #0, rb+1
+                    let a2 = match self.relative(1) { Some(a) if !is_code(a) => a, _ => return self.interpret(outputs) };
+                    let v = match i64::checked_add(self.read(78), 0) { Some(v) => v, None => return self.interpret(outputs) };
+                    self.write(a2, v);
+                    self.pc = 10;
+                }
+                10 => {
+                    // ADD #17, #0, rb+0
+                    let a2 = match self.relative(0) { Some(a) if !is_code(a) => a, _ => return self.interpret(outputs) };
+                    let v = match i64::checked_add(17, 0) { Some(v) => v, None => return self.interpret(outputs) };
+                    self.write(a2, v);
+                    self.pc = 14;
+                }
+                14 => {
+                    // JT #1, #66
+                    if 1 != 0 {
+                        let t = 66;
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 17;
+                    }
+                }
+                17 => {
+                    // OUT rb+1
+                    let a0 = match self.relative(1) { Some(a) => a, None => return self.interpret(outputs) };
+                    outputs.push(self.read(a0));
+                    self.pc = 19;
+                    return Ok(IntCodeResult::Output);
+                }
+                19 => {
+                    // ADD [78], #1, [24]
+                    let v = match i64::checked_add(self.read(78), 1) { Some(v) => v, None => return self.interpret(outputs) };
+                    self.write(24, v);
+                    self.pc = 23;
+                }
+                23 => {
+                    // OUT #0
+                    outputs.push(self.read(24));
+                    self.pc = 25;
+                    return Ok(IntCodeResult::Output);
+                }
+                25 => {
+                    // EQ [77], #1, [79]
+                    let v = if self.read(77) == 1 { 1 } else { 0 };
+                    self.write(79, v);
+                    self.pc = 29;
+                }
+                29 => {
+                    // JT [79], #56
+                    if self.read(79) != 0 {
+                        let t = 56;
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 32;
+                    }
+                }
+                32 => {
+                    // EQ [77], #2, [79]
+                    let v = if self.read(77) == 2 { 1 } else { 0 };
+                    self.write(79, v);
+                    self.pc = 36;
+                }
+                36 => {
+                    // JT [79], #61
+                    if self.read(79) != 0 {
+                        let t = 61;
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 39;
+                    }
+                }
+                39 => {
+                    // LT [78], #0, [79]
+                    let v = if self.read(78) < 0 { 1 } else { 0 };
+                    self.write(79, v);
+                    self.pc = 43;
+                }
+                43 => {
+                    // JF [79], #47
+                    if self.read(79) == 0 {
+                        let t = 47;
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 46;
+                    }
+                }
+                46 => {
+                    // HLT
+                    return Ok(IntCodeResult::Halt);
+                }
+                47 => {
+                    // MUL [78], #2, [78]
+                    let v = match i64::checked_mul(self.read(78), 2) { Some(v) => v, None => return self.interpret(outputs) };
+                    self.write(78, v);
+                    self.pc = 51;
+                }
+                51 => {
+                    // OUT [78]
+                    outputs.push(self.read(78));
+                    self.pc = 53;
+                    return Ok(IntCodeResult::Output);
+                }
+                53 => {
+                    // JT #1, #47
+                    if 1 != 0 {
+                        let t = 47;
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 56;
+                    }
+                }
+                56 => {
+                    // ARB [80]
+                    self.rb = match self.rb.checked_add(self.read(80)) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 58;
+                }
+                58 => {
+                    // ARB #1
+                    self.rb = match self.rb.checked_add(1) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 60;
+                }
+                60 => {
+                    // HLT
+                    return Ok(IntCodeResult::Halt);
+                }
+                61 => {
+                    // ARB [80]
+                    self.rb = match self.rb.checked_add(self.read(80)) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 63;
+                }
+                63 => {
+                    // OUT rb+1
+                    let a0 = match self.relative(1) { Some(a) => a, None => return self.interpret(outputs) };
+                    outputs.push(self.read(a0));
+                    self.pc = 65;
+                    return Ok(IntCodeResult::Output);
+                }
+                65 => {
+                    // HLT
+                    return Ok(IntCodeResult::Halt);
+                }
+                66 => {
+                    // ARB #2
+                    self.rb = match self.rb.checked_add(2) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 68;
+                }
+                68 => {
+                    // MUL rb-1, rb-1, rb-1
+                    let a0 = match self.relative(-1) { Some(a) => a, None => return self.interpret(outputs) };
+                    let a1 = match self.relative(-1) { Some(a) => a, None => return self.interpret(outputs) };
+                    let a2 = match self.relative(-1) { Some(a) if !is_code(a) => a, _ => return self.interpret(outputs) };
+                    let v = match i64::checked_mul(self.read(a0), self.read(a1)) { Some(v) => v, None => return self.interpret(outputs) };
+                    self.write(a2, v);
+                    self.pc = 72;
+                }
+                72 => {
+                    // ARB #-2
+                    self.rb = match self.rb.checked_add(-2) { Some(rb) => rb, None => return self.interpret(outputs) };
+                    self.pc = 74;
+                }
+                74 => {
+                    // JT #1, rb+0
+                    let a1 = match self.relative(0) { Some(a) => a, None => return self.interpret(outputs) };
+                    if 1 != 0 {
+                        let t = self.read(a1);
+                        if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }
+                        self.pc = t as usize;
+                    } else {
+                        self.pc = 77;
+                    }
+                }
+_ => return self.interpret(outputs),
+            }
+        }
+    }
+
+    fn interpret(&mut self, outputs: &mut Vec<i64>) -> Result<IntCodeResult, IntCodeError> {
+        let mut state = State::new(std::mem::take(&mut self.mem));
+        state.ic = self.pc;
+        state.relative_base = self.rb;
+        state.inputs = std::mem::take(&mut self.inputs);
+
+        let res = state.run(outputs);
+        self.fallback = Some(state);
+        res
+    }
+
+    fn read(&self, address: usize) -> i64 {
+        self.mem.get(address).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, address: usize, value: i64) {
+        if self.mem.len() <= address {
+            self.mem.resize(address + 1, 0);
+        }
+        self.mem[address] = value;
+    }
+
+    fn relative(&self, offset: i64) -> Option<usize> {
+        match self.rb.checked_add(offset) {
+            Some(address) if address >= 0 => Some(address as usize),
+            _ => None,
+        }
+    }
+}
+
+fn is_code(address: usize) -> bool {
+    match address {
+        0..=23 | 25..=76 => true,
+        _ => false,
+    }
+}
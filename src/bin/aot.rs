@@ -0,0 +1,21 @@
+use aoc2019::intcode::aot::transpile;
+use aoc2019::intcode::parse_program;
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+
+    let path = args
+        .next()
+        .ok_or_else(|| format_err!("Usage: aot <program file> [intcode module path]"))?;
+    let intcode_path = args
+        .next()
+        .unwrap_or_else(|| "aoc2019::intcode".to_string());
+
+    let program = parse_program(&read_to_string(&path)?)?;
+
+    print!("{}", transpile(&program, &intcode_path));
+
+    Ok(())
+}
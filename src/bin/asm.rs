@@ -0,0 +1,16 @@
+use aoc2019::intcode::asm::assemble;
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+
+fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| format_err!("Usage: asm <source file>"))?;
+
+    let program = assemble(&read_to_string(&path)?)?;
+
+    let words: Vec<String> = program.iter().map(|v| v.to_string()).collect();
+    println!("{}", words.join(","));
+
+    Ok(())
+}
@@ -0,0 +1,139 @@
+use aoc2019::intcode::disasm::Decoded;
+use aoc2019::intcode::{parse_program, IntCodeResult, State};
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+use std::hint::black_box;
+use std::time::Instant;
+
+// The interpreter used to decode every instruction with `get_opcode_and_parmode` below, which
+// allocates a vector of parameter modes, and now decodes in place with `Decoded::new`. The old
+// decoder cannot be swapped back into the interpreter, so both decoders are timed on their own
+// over the instruction words executed by each workload. The difference is the time the old
+// decoder would add to the measured run.
+
+/// Number of times each measurement is repeated, keeping the fastest.
+const REPEAT: usize = 20;
+
+/// The decoder used before, splitting an instruction word into its opcode and its parameter
+/// modes.
+fn get_opcode_and_parmode(word: i64) -> (i64, Vec<u8>) {
+    let mut v = word;
+
+    let opcode = v % 100;
+    v /= 100;
+
+    let mut parmodes = Vec::new();
+
+    while v > 0 {
+        let pm = v % 10;
+        parmodes.push(pm as u8);
+        v /= 10;
+    }
+
+    (opcode, parmodes)
+}
+
+/// Run the day 9 BOOST program in sensor boost mode, passing every executed instruction word
+/// to `executed`.
+fn boost(program: &[i64], executed: &mut dyn FnMut(i64)) -> Result<()> {
+    let mut state = State::new(program.to_vec());
+    state.inputs.push_back(2);
+
+    let mut outputs = Vec::new();
+    loop {
+        executed(state.memory[state.ic]);
+        if state.step(&mut outputs)? == Some(IntCodeResult::Halt) {
+            return Ok(());
+        }
+    }
+}
+
+/// Scan the day 19 tractor beam, starting the program afresh for every position.
+fn beam_scan(program: &[i64], executed: &mut dyn FnMut(i64)) -> Result<()> {
+    let mut outputs = Vec::new();
+    for i in 0..50 {
+        for j in 0..50 {
+            let mut state = State::new(program.to_vec());
+            state.inputs.push_back(j);
+            state.inputs.push_back(i);
+            loop {
+                executed(state.memory[state.ic]);
+                match state.step(&mut outputs)? {
+                    Some(IntCodeResult::Halt) => break,
+                    Some(IntCodeResult::Input) => return Err(format_err!("Not enough input")),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The fastest of `REPEAT` runs of `f`, in seconds.
+fn best_of<F: FnMut() -> Result<()>>(mut f: F) -> Result<f64> {
+    let mut best = f64::INFINITY;
+    for _ in 0..REPEAT {
+        let start = Instant::now();
+        f()?;
+        best = best.min(start.elapsed().as_secs_f64());
+    }
+    Ok(best)
+}
+
+type Workload = fn(&[i64], &mut dyn FnMut(i64)) -> Result<()>;
+
+fn measure(name: &str, program: &[i64], f: Workload) -> Result<()> {
+    let mut words = Vec::new();
+    f(program, &mut |w| words.push(w))?;
+    let steps = words.len();
+
+    let run = best_of(|| {
+        f(program, &mut |w| {
+            black_box(w);
+        })
+    })?;
+    let allocating = best_of(|| {
+        for &w in &words {
+            black_box(get_opcode_and_parmode(black_box(w)));
+        }
+        Ok(())
+    })?;
+    let in_place = best_of(|| {
+        for &w in &words {
+            black_box(Decoded::new(black_box(w)));
+        }
+        Ok(())
+    })?;
+
+    let before = steps as f64 / (run + allocating - in_place);
+    let after = steps as f64 / run;
+    println!(
+        "{:12} {:10} instructions in {:8.4}s = {:12.0} instructions/s",
+        name, steps, run, after
+    );
+    println!(
+        "{:12} decoding takes {:6.2}ns per instruction allocating, {:6.2}ns in place",
+        name,
+        allocating / steps as f64 * 1e9,
+        in_place / steps as f64 * 1e9
+    );
+    println!(
+        "{:12} estimated {:12.0} instructions/s with the allocating decoder, {:+.1}% now",
+        name,
+        before,
+        (after / before - 1.0) * 100.0
+    );
+
+    Ok(())
+}
+
+fn main() -> Result<()> {
+    let day09 = parse_program(&read_to_string("data/day09/input")?)?;
+    let day19 = parse_program(&read_to_string("data/day19/input")?)?;
+
+    measure("day09 boost", &day09, boost)?;
+    measure("day19 scan", &day19, beam_scan)?;
+
+    Ok(())
+}
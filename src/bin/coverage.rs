@@ -0,0 +1,44 @@
+use aoc2019::intcode::{parse_program, IntCodeResult, State};
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args.next().ok_or_else(|| {
+        format_err!("Usage: coverage <program file> [comma-separated inputs for each run...]")
+    })?;
+
+    let program = parse_program(&read_to_string(&path)?)?;
+    let mut runs: Vec<Vec<i64>> = args.map(|a| parse_program(&a)).collect::<Result<_>>()?;
+    if runs.is_empty() {
+        runs.push(Vec::new());
+    }
+
+    // every run restarts the same machine, which keeps the coverage collected so far
+    let mut state = State::new(program.clone());
+    state.enable_coverage();
+
+    for inputs in runs {
+        state.restart(&program);
+        state.inputs = inputs.into();
+
+        let mut outputs = Vec::new();
+        loop {
+            match state.run(&mut outputs)? {
+                IntCodeResult::Halt => break,
+                IntCodeResult::Input => {
+                    println!("; run stopped waiting for input");
+                    break;
+                }
+                _ => {}
+            }
+        }
+        println!("; outputs: {:?}", outputs);
+    }
+
+    if let Some(coverage) = state.coverage.as_ref() {
+        print!("{}", coverage.listing(&program));
+    }
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::convert::TryFrom;
 
+use aoc2019::intcode::io::{Input, Output};
 use aoc2019::intcode::{parse_program, IntCodeResult, State};
 use aoc2019::result::{format_err, Error, Result};
 use aoc2019::util::read_to_string;
@@ -56,6 +57,8 @@ impl std::fmt::Display for Tile {
 #[derive(Debug)]
 struct Board {
     tiles: HashMap<Position, Tile>,
+    /// Outputs of an incomplete tile update.
+    pending: Vec<i64>,
 }
 
 impl std::fmt::Display for Board {
@@ -97,6 +100,7 @@ impl Board {
     fn new() -> Self {
         Board {
             tiles: HashMap::new(),
+            pending: Vec::new(),
         }
     }
 
@@ -108,58 +112,14 @@ impl Board {
         self.tiles.insert(pos.clone(), tile);
     }
 
-    fn run(&mut self, program: &Vec<i64>) -> Result<()> {
-        let inputs = Vec::new();
-        let mut state = State {
-            memory: program.clone(),
-            ic: 0,
-            inputs,
-            relative_base: 0,
-        };
+    fn run(&mut self, program: &[i64]) -> Result<()> {
+        let mut state = State::new(program.to_vec());
 
         // run to completion
-        let mut outputs = Vec::new();
-        loop {
-            match state.run(&mut outputs)? {
-                IntCodeResult::Halt => break,
-                IntCodeResult::Input => {
-                    let ball_pos = self.get_pos_of(Tile::Ball).unwrap();
-                    let paddle_pos = self.get_pos_of(Tile::Paddle).unwrap();
-
-                    let delta_j = ball_pos.j - paddle_pos.j;
-
-                    let v = if delta_j < 0 {
-                        -1
-                    } else if delta_j == 0 {
-                        0
-                    } else {
-                        1
-                    };
-                    state.inputs.push(v);
-                    println!("{}\nINPUT: {}", self, v);
-                }
-                IntCodeResult::Output => {
-                    if outputs.len() >= 3 {
-                        let j = outputs.remove(0);
-                        let i = outputs.remove(0);
-                        let t = outputs.remove(0);
-
-                        if i == 0 && j == -1 {
-                            println!("NEW SCORE: {}", t);
-                        } else {
-                            let pos = Position { i, j };
-                            let tile = Tile::try_from(t)?;
-
-                            self.set(&pos, tile);
-                        }
-
-                        //println!("Mov {}, direction {:?}", self.robot_pos, self.robot_dir);
-                    }
-                }
-            }
+        match state.run_with(&mut *self)? {
+            IntCodeResult::Halt => Ok(()),
+            res => Err(format_err!("Unexpected result: {:?}", res)),
         }
-
-        Ok(())
     }
 
     fn count(&self) -> HashMap<&Tile, usize> {
@@ -182,6 +142,42 @@ impl Board {
     }
 }
 
+impl Input for Board {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        let ball_pos = self
+            .get_pos_of(Tile::Ball)
+            .ok_or_else(|| format_err!("No ball"))?;
+        let paddle_pos = self
+            .get_pos_of(Tile::Paddle)
+            .ok_or_else(|| format_err!("No paddle"))?;
+
+        let v = (ball_pos.j - paddle_pos.j).signum();
+        println!("{}\nINPUT: {}", self, v);
+
+        Ok(Some(v))
+    }
+}
+
+impl Output for Board {
+    fn output(&mut self, value: i64) -> Result<()> {
+        self.pending.push(value);
+        if self.pending.len() < 3 {
+            return Ok(());
+        }
+
+        let (j, i, t) = (self.pending[0], self.pending[1], self.pending[2]);
+        self.pending.clear();
+
+        if i == 0 && j == -1 {
+            println!("NEW SCORE: {}", t);
+        } else {
+            self.set(&Position { i, j }, Tile::try_from(t)?);
+        }
+
+        Ok(())
+    }
+}
+
 fn main() -> Result<()> {
     let mut board = Board::new();
     let mut program = parse_program(&read_to_string("data/day13/input")?)?;
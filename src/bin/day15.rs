@@ -1,8 +1,10 @@
 use aoc2019::board::{Board, Direction, Position};
-use aoc2019::intcode::{parse_program, State};
+use aoc2019::intcode::explore::{explore, Node, Strategy, Visit};
+use aoc2019::intcode::memory::{Memory, PagedMemory};
+use aoc2019::intcode::{parse_program, IntCodeResult, State};
 use aoc2019::result::{format_err, Result};
 use aoc2019::util::read_to_string;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 
 #[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
 enum Tile {
@@ -42,119 +44,82 @@ impl std::fmt::Display for Tile {
     }
 }
 
-enum BfsResult {
-    Found { pos: Position, path: Vec<Direction> },
-    NotFound { max_path_len: usize },
-}
+/// Move the droid one step, returning the tile it moved to or bumped into.
+fn move_droid(state: &mut State<PagedMemory>, direction: Direction) -> Result<Tile> {
+    state.inputs.push_back(direction.to_input());
 
-trait Bfs {
-    fn search_bfs(&self, stat_pos: &Position, target: &Tile) -> BfsResult;
-    fn run_discovery(&mut self, program: &Vec<i64>) -> Result<()>;
+    let mut outputs = Vec::new();
+    match state.run(&mut outputs)? {
+        IntCodeResult::Output => Tile::from_output(outputs[0]),
+        res => Err(format_err!("Unexpected result: {:?}", res)),
+    }
 }
 
-impl Bfs for Board<Tile> {
-    fn search_bfs(&self, start_pos: &Position, target: &Tile) -> BfsResult {
-        // this is breadth first search for a tile of interest, given the current knowledge
-        let mut queue = vec![(start_pos.to_owned(), Vec::new())];
-        let mut seen = HashSet::new();
-
-        let mut max_path_len = 0;
-
-        seen.insert(start_pos.clone());
-
-        while !queue.is_empty() {
-            let (cur, history) = queue.remove(0);
-            seen.insert(cur.clone());
-            let tile = self.get(&cur);
-
-            if &tile == target {
-                return BfsResult::Found {
-                    pos: cur,
-                    path: history,
-                };
-            } else if tile == Tile::Wall {
-                // don't extend search from this tile
-                continue;
-            }
-
-            for dir in Direction::ALL.iter() {
-                let (i_ofs, j_ofs) = dir.to_ofs();
-
-                let cand_pos = Position {
-                    i: cur.i + i_ofs,
-                    j: cur.j + j_ofs,
-                };
-
-                if !seen.contains(&cand_pos) {
-                    let mut new_history: Vec<Direction> = history.iter().map(|h| *h).collect();
-                    new_history.push(*dir);
-
-                    if new_history.len() > max_path_len {
-                        max_path_len = new_history.len();
-                    }
+fn position(start: Position, path: &[Direction]) -> Position {
+    path.iter().fold(start, |pos, d| pos + d.to_ofs().into())
+}
 
-                    queue.push((cand_pos, new_history));
-                }
+/// Explore the area reachable from `start`, recording all tiles seen on `board`. Returns the
+/// droid when it has reached `target` with the path it took, or `None` after exploring
+/// everything. The distance of every open position from `start` is recorded in `distances`.
+fn discover(
+    board: &mut Board<Tile>,
+    distances: &mut HashMap<Position, usize>,
+    droid: State<PagedMemory>,
+    start: Position,
+    target: Option<Tile>,
+) -> Result<Option<Node<PagedMemory, Direction>>> {
+    explore(
+        Strategy::BreadthFirst,
+        droid,
+        start,
+        Direction::ALL.to_vec(),
+        |state, path| {
+            let pos = position(start, path);
+            let tile = move_droid(state, *path.last().unwrap())?;
+            board.set(&pos, tile);
+
+            if tile == Tile::Wall {
+                return Ok(Visit::Prune);
             }
-        }
-
-        BfsResult::NotFound { max_path_len }
-    }
-
-    fn run_discovery(&mut self, program: &Vec<i64>) -> Result<()> {
-        // fill up knowledge of the playfield
-        while let BfsResult::Found { pos, path } = self.search_bfs(&Position::ZERO, &Tile::Unknown)
-        {
-            let n_steps = path.len();
-            let mut state = State {
-                memory: program.clone(),
-                ic: 0,
-                inputs: path.iter().map(|d| d.to_input()).collect(),
-                relative_base: 0,
-            };
-
-            let mut outputs = Vec::new();
-            for _ in 0..n_steps {
-                state.run(&mut outputs)?;
+            if Some(tile) == target {
+                return Ok(Visit::Found);
             }
 
-            //println!(
-            //    "Target {}: Path {:?} gives outputs {:?}",
-            //    pos, path, outputs
-            //);
-
-            let last_output = outputs.pop().unwrap();
-            let tile = Tile::from_output(last_output)?;
-
-            self.set(&pos, tile);
-
-            println!("\n\n{}", self);
-        }
-
-        Ok(())
-    }
+            distances.entry(pos).or_insert_with(|| path.len());
+            Ok(Visit::Expand {
+                key: pos,
+                actions: Direction::ALL.to_vec(),
+            })
+        },
+    )
 }
 
 fn main() -> Result<()> {
     let program = parse_program(&read_to_string("data/day15/input")?)?;
-
-    println!("Program: {:?}", program);
+    let droid = State::with_memory(PagedMemory::from_program(program));
 
     let mut board = Board::new();
     board.set(&Position::ZERO, Tile::Empty);
-    board.run_discovery(&program)?;
 
-    println!("BOARD:\n{}", board);
+    let found = discover(
+        &mut board,
+        &mut HashMap::new(),
+        droid,
+        Position::ZERO,
+        Some(Tile::OxygenSystem),
+    )?;
+    let Node { state, path } = found.ok_or_else(|| format_err!("No oxygen system found"))?;
+    println!("Path: {:?} ({} steps)", path, path.len());
 
-    if let BfsResult::Found { pos: o2s_pos, path } =
-        board.search_bfs(&Position::ZERO, &Tile::OxygenSystem)
-    {
-        println!("Path: {:?} ({} steps)", path, path.len());
+    let o2s_pos = position(Position::ZERO, &path);
+    let mut distances = HashMap::new();
+    discover(&mut board, &mut distances, state, o2s_pos, None)?;
 
-        if let BfsResult::NotFound { max_path_len } = board.search_bfs(&o2s_pos, &Tile::Unknown) {
-            println!("Flooding time: {} minutes", max_path_len - 1);
-        }
-    }
+    println!("BOARD:\n{}", board);
+
+    let minutes = distances.values().max().copied().unwrap_or(0);
+    println!("Flooding time: {} minutes", minutes);
 
     Ok(())
 }
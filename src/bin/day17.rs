@@ -1,6 +1,7 @@
 use aoc2019::board::{Board, Direction, Position};
+use aoc2019::intcode::ascii::{Ascii, Read};
 use aoc2019::intcode::{parse_program, IntCodeResult, State};
-use aoc2019::result::{format_err, Error, Result};
+use aoc2019::result::{format_err, Result};
 use aoc2019::util::read_to_string;
 use itertools::Itertools;
 use std::collections::HashMap;
@@ -46,36 +47,22 @@ impl std::fmt::Display for Tile {
 }
 
 fn read_camera(state: &mut State) -> Result<Board<Tile>> {
-    let mut i = 0;
-    let mut j = 0;
+    let mut camera = Ascii::new(state);
     let mut board = Board::new();
 
-    let mut outputs = Vec::new();
-
+    let mut i = 0;
     loop {
-        match state.run(&mut outputs)? {
-            IntCodeResult::Input => {
-                panic!("Unexpected input");
-            }
-            IntCodeResult::Output => {
-                let o = outputs.pop().unwrap();
-                match o {
-                    10 => {
-                        i += 1;
-                        j = 0;
-                    }
-                    _ => {
-                        let tile = Tile::from_output(o)?;
-                        board.set(&Position { i, j }, tile);
-                        j += 1;
-                    }
+        match camera.read_line()? {
+            Read::Text(line) => {
+                for (j, c) in line.chars().enumerate() {
+                    board.set(&Position { i, j: j as i64 }, Tile::from_output(c as i64)?);
                 }
+                i += 1;
             }
-            IntCodeResult::Halt => break,
+            Read::Stopped(IntCodeResult::Halt) => return Ok(board),
+            Read::Stopped(res) => return Err(format_err!("Unexpected result: {:?}", res)),
         }
     }
-
-    Ok(board)
 }
 
 fn find_intersections(board: &Board<Tile>) -> Vec<Position> {
@@ -106,17 +93,11 @@ enum Steering {
 }
 
 impl Steering {
-    fn to_instr(&self, input: &mut Vec<i64>) {
+    fn to_command(self) -> String {
         match self {
-            Steering::Left => input.push(76),
-            Steering::Right => input.push(82),
-            Steering::Walk { steps } => {
-                let digits = format!("{}", steps);
-
-                for d in digits.chars() {
-                    input.push(d as i64);
-                }
-            }
+            Steering::Left => "L".to_owned(),
+            Steering::Right => "R".to_owned(),
+            Steering::Walk { steps } => format!("{}", steps),
         }
     }
 }
@@ -214,32 +195,19 @@ fn cover_by_phrasebook(path: &Vec<Steering>, phrases: &Vec<&Vec<Steering>>) -> O
     Some(out)
 }
 
-fn encode_main(main: &[usize], out: &mut Vec<i64>) {
-    for (i, v) in main.iter().enumerate() {
-        if i > 0 {
-            out.push(44);
-        }
-        let instr = match v {
-            0 => 65,
-            1 => 66,
-            2 => 67,
+fn encode_main(main: &[usize]) -> String {
+    main.iter()
+        .map(|v| match v {
+            0 => "A",
+            1 => "B",
+            2 => "C",
             _ => panic!("Unknown instruction {}", v),
-        };
-
-        out.push(instr);
-    }
-    out.push(10);
+        })
+        .join(",")
 }
 
-fn encode_func(f: &[Steering], mut out: &mut Vec<i64>) {
-    for (i, v) in f.iter().enumerate() {
-        if i > 0 {
-            out.push(44);
-        }
-
-        v.to_instr(&mut out);
-    }
-    out.push(10);
+fn encode_func(f: &[Steering]) -> String {
+    f.iter().map(|v| v.to_command()).join(",")
 }
 
 fn main() -> Result<()> {
@@ -293,46 +261,35 @@ fn main() -> Result<()> {
             let b = phrasebook[1];
             let c = phrasebook[2];
 
-            let mut input = Vec::new();
-            encode_main(&main, &mut input);
-            encode_func(&a[..], &mut input);
-            encode_func(&b[..], &mut input);
-            encode_func(&c[..], &mut input);
-            input.push(110); // n - no video feed;
-            input.push(10);
-
-            let inp = input.iter().map(|v| *v as u8).collect();
-            let inp = String::from_utf8(inp)?;
-            println!("Input is:\n{}", inp);
+            let input = vec![
+                encode_main(&main),
+                encode_func(&a[..]),
+                encode_func(&b[..]),
+                encode_func(&c[..]),
+                "n".to_owned(), // no video feed
+            ];
+            println!("Input is:\n{}", input.join("\n"));
 
             let mut program2 = program.clone();
             program2[0] = 2;
 
-            let mut state = State {
-                memory: program2.clone(),
-                ic: 0,
-                inputs: input.clone(),
-                relative_base: 0,
-            };
-
-            //let board = read_camera(&mut state)?;
-
-            //println!("Board: {}", board);
-
-            let mut output = Vec::new();
-            loop {
-                match state.run(&mut output)? {
-                    IntCodeResult::Output => {}
-                    IntCodeResult::Input => panic!("Insufficient input"),
-                    IntCodeResult::Halt => break,
-                }
+            let mut state = State::new(program2.clone());
+            let mut robot = Ascii::new(&mut state);
+            for line in &input {
+                robot.send_line(line)?;
             }
 
-            let out = output.iter().map(|v| *v as u8).collect();
-            let out = String::from_utf8(out)?;
+            match robot.run()? {
+                IntCodeResult::Halt => {}
+                IntCodeResult::Input => panic!("Insufficient input"),
+                res => return Err(format_err!("Unexpected result: {:?}", res)),
+            }
 
-            println!("Output:\n{}", out);
-            println!("Final Output: {}", output[output.len() - 1]);
+            println!("Output:\n{}", robot.take_text());
+            match robot.answers.last() {
+                Some(dust) => println!("Final Output: {}", dust),
+                None => println!("No dust collected"),
+            }
 
             break;
         }
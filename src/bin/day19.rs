@@ -41,8 +41,8 @@ impl std::fmt::Display for Tile {
 
 fn read_drone(prog: &Vec<i64>, i: i64, j: i64) -> Result<Tile> {
     let mut state = State::new(prog.clone());
-    state.inputs.push(j);
-    state.inputs.push(i);
+    state.inputs.push_back(j);
+    state.inputs.push_back(i);
 
     let mut out = Vec::new();
     state.run(&mut out)?;
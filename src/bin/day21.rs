@@ -1,55 +1,26 @@
+use aoc2019::intcode::ascii::Ascii;
 use aoc2019::intcode::{parse_program, IntCodeResult, State};
-use aoc2019::result::{format_err, Error, Result};
+use aoc2019::result::{format_err, Result};
 use aoc2019::util::read_to_string;
 
-fn encode_springscript(s: &str) -> Vec<i64> {
-    let mut out = Vec::new();
-
-    for line in s.lines() {
-        let mut line_empty = true;
-
-        for c in line.trim().chars() {
-            if c == '#' {
-                break;
-            }
-
-            out.push(c as i64);
-            line_empty = false;
-        }
-
-        if !line_empty {
-            out.push(10);
-        }
-    }
-
-    out
-}
-
-fn render(out: &Vec<i64>) {
-    for c in out {
-        print!("{}", (*c as u8) as char);
-    }
-}
-
-fn run(intcode: &Vec<i64>, springscript: &str) -> Result<()> {
-    let inputs = encode_springscript(springscript.trim());
+fn run(intcode: &[i64], springscript: &str) -> Result<()> {
     let mut state = State::new(intcode.to_vec());
-    state.inputs.extend(inputs);
-    let mut output = Vec::new();
-    loop {
-        match state.run(&mut output)? {
-            IntCodeResult::Input => panic!("Not enough input! "),
-            IntCodeResult::Output => {}
-            IntCodeResult::Halt => {
-                break;
-            }
-        }
+    let mut ascii = Ascii::new(&mut state);
+    ascii.send_script(springscript)?;
+
+    match ascii.run()? {
+        IntCodeResult::Halt => {}
+        IntCodeResult::Input => return Err(format_err!("Not enough input!")),
+        res => return Err(format_err!("Unexpected result: {:?}", res)),
     }
 
     println!("Output:");
-    render(&output);
+    print!("{}", ascii.take_text());
 
-    println!("{}", output[output.len() - 1]);
+    match ascii.answers.last() {
+        Some(damage) => println!("{}", damage),
+        None => println!("The droid fell into space"),
+    }
 
     Ok(())
 }
@@ -1,99 +1,69 @@
-use aoc2019::board::{Board, Direction, Position};
+use aoc2019::intcode::ascii::{commands, Ascii};
+use aoc2019::intcode::explore::{explore, Strategy, Visit};
+use aoc2019::intcode::memory::{Memory, PagedMemory};
 use aoc2019::intcode::{parse_program, IntCodeResult, State};
-use aoc2019::result::{format_err, Error, Result};
+use aoc2019::result::{format_err, Result};
 use aoc2019::util::read_to_string;
-use itertools::Itertools;
-use std::io::stdin;
 
-fn encode(s: &str) -> Vec<i64> {
-    let mut out = Vec::new();
-
-    for mut line in s.lines() {
-        if let Some(p) = line.find("#") {
-            line = &line[0..p].trim();
-        }
-
-        let mut line_empty = true;
-
-        for c in line.trim().chars() {
-            out.push(c as i64);
-            line_empty = false;
-        }
-
-        if !line_empty {
-            out.push(10);
-        }
+/// Send `command` to the droid, and run it until it asks for the next one. Returns the reason
+/// it stopped, and its output. Watchpoint hits and exhausted run budgets do not stop it.
+fn send(state: &mut State<PagedMemory>, command: &str) -> Result<(IntCodeResult, String)> {
+    let mut droid = Ascii::new(state);
+    if !command.is_empty() {
+        droid.send_line(command)?;
     }
 
-    out
-}
-
-fn render(out: &Vec<i64>) {
-    for c in out {
-        print!("{}", (*c as u8) as char);
+    loop {
+        match droid.run()? {
+            IntCodeResult::Watch(_) | IntCodeResult::BudgetExhausted => {}
+            res => return Ok((res, droid.take_text())),
+        }
     }
 }
 
-fn run(intcode: &Vec<i64>, springscript: &str) -> Result<()> {
-    let mut lines: Vec<&str> = springscript.trim().split("\n").collect();
-
-    let inp = lines.remove(0).trim();
-    println!(">> {} {:?}", inp, encode(inp));
-
-    let mut state = State::new(intcode.to_vec());
-    state.inputs.extend(encode(inp));
-    let mut output = Vec::new();
-
-    'outer: loop {
-        loop {
-            match state.run(&mut output)? {
-                IntCodeResult::Input => {
-                    break;
-                }
-                IntCodeResult::Output => {}
-                IntCodeResult::Halt => {
-                    render(&output);
-                    break 'outer;
-                }
-            }
-        }
-        render(&output);
-
-        output.clear();
-
-        if lines.is_empty() {
-            let mut l = String::new();
-            stdin().read_line(&mut l)?;
-            let inp = encode(l.trim());
+/// Try to pass the pressure-sensitive floor south of the checkpoint, by dropping subsets of
+/// `items`. Returns the output once the droid got through.
+fn pass_checkpoint(state: State<PagedMemory>, items: &[&str]) -> Result<Option<String>> {
+    let mut passed = None;
 
-            state.inputs.extend(inp);
-        } else {
-            let inp = lines.remove(0).trim();
-            println!(">> {}", inp);
-            state.inputs.extend(encode(inp));
-        }
+    // first try with all items
+    let (res, out) = send(&mut state.fork(), "south")?;
+    if res == IntCodeResult::Halt {
+        return Ok(Some(out));
     }
 
-    Ok(())
-}
-
-fn make_combinations(items: &[&str]) -> String {
-    let mut out = String::new();
-    for n in 1..=items.len() {
-        for combo in items.iter().combinations(n) {
-            out += &format!("#combination: {:?}\n", combo);
-            for i in items.iter() {
-                out += &format!("drop {}\n", i);
+    // every set of dropped items, identified by a bit mask, is tried once, by dropping items
+    // in the order they are listed
+    explore(
+        Strategy::DepthFirst,
+        state,
+        0_u32,
+        (0..items.len()).collect(),
+        |state, path| {
+            let last = *path.last().unwrap();
+            send(state, &format!("drop {}", items[last]))?;
+
+            let dropped: Vec<_> = path.iter().map(|&i| items[i]).collect();
+            let (res, out) = send(state, "south")?;
+
+            if res == IntCodeResult::Halt {
+                println!("Dropping {:?} works", dropped);
+                passed = Some(out);
+                Ok(Visit::Found)
+            } else if out.contains("Droids on this ship are lighter") {
+                println!("Dropping {:?} is not enough", dropped);
+                Ok(Visit::Expand {
+                    key: path.iter().fold(0, |mask, i| mask | 1 << i),
+                    actions: (last + 1..items.len()).collect(),
+                })
+            } else {
+                println!("Dropping {:?} is too much", dropped);
+                Ok(Visit::Prune)
             }
+        },
+    )?;
 
-            for i in combo.iter() {
-                out += &format!("take {}\n", i);
-            }
-
-            out += "south\n";
-        }
-    }
-    out
+    Ok(passed)
 }
 
 fn main() -> Result<()> {
@@ -141,7 +111,8 @@ fn main() -> Result<()> {
 
     println!("PART ONE");
 
-    let mut prog = "
+    // the route to the checkpoint, picking up all safe items, unless a command file is given
+    let route = "
     east                # 5
     east                # 6
     east                # 7
@@ -175,15 +146,34 @@ fn main() -> Result<()> {
     north               # 2
     take weather machine
     east                # 3
-    "
-    .trim()
-    .to_owned();
-
-    let combos = make_combinations(&items);
-
-    prog += &combos.trim();
+    ";
+    let prog = match std::env::args().nth(1) {
+        Some(path) => read_to_string(&path)?,
+        None => route.to_owned(),
+    };
+
+    let mut state = State::with_memory(PagedMemory::from_program(intcode));
+    state.enable_loop_detection();
+
+    let (_, out) = send(&mut state, "")?;
+    print!("{}", out);
+
+    for command in commands(&prog) {
+        println!(">> {}", command);
+
+        match send(&mut state, command)? {
+            (IntCodeResult::Input, out) => print!("{}", out),
+            (res, out) => {
+                print!("{}", out);
+                return Err(format_err!("Unexpected result: {:?}", res));
+            }
+        }
+    }
 
-    run(&intcode, &prog)?;
+    match pass_checkpoint(state, &items)? {
+        Some(out) => print!("{}", out),
+        None => println!("No combination of items passes the checkpoint"),
+    }
 
     Ok(())
 }
@@ -0,0 +1,294 @@
+use aoc2019::intcode::debugger::{Debugger, StopReason};
+use aoc2019::intcode::disasm::decode;
+use aoc2019::intcode::{parse_program, State};
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+use std::io::{stdin, stdout, BufRead, Write};
+
+const HELP: &str = "
+Commands:
+  s [n]           step n instructions (default 1)
+  c               continue until breakpoint, input or halt
+  rs [n]          step back n instructions (default 1)
+  rio             step back to before the last input or output
+  u <addr>        run until the instruction counter reaches addr
+  b <addr>        set breakpoint          d <addr>   delete breakpoint
+  bl              list breakpoints
+  wr <addr> [n]   watch reads of n cells  ww <addr> [n]  watch writes of n cells
+  wl              list watchpoints        wc         clear watchpoints
+  l [addr] [n]    disassemble n instructions starting at addr (default: ic)
+  x <addr> [n]    examine n memory cells starting at addr
+  w <addr> <val>  write memory cell
+  ic <addr>       set instruction counter
+  rb <val>        set relative base
+  i <v> [v ...]   queue numeric inputs
+  a <text>        queue a line of ASCII input
+  iq              show input queue      iqc   clear input queue
+  r               show registers
+  o               toggle stopping on every output
+  save <path>     save a snapshot         load <path>  restore a snapshot
+  h               show this help        q     quit
+";
+
+const HISTORY_LIMIT: usize = 1_000_000;
+
+fn parse_num<T: std::str::FromStr>(s: Option<&str>, what: &str) -> Result<T> {
+    let s = s.ok_or_else(|| format_err!("Missing argument: {}", what))?;
+    s.parse()
+        .map_err(|_| format_err!("Invalid {}: {}", what, s))
+}
+
+/// The end of the address range of `n` cells starting at `addr`.
+fn range_end(addr: usize, n: usize) -> Result<usize> {
+    addr.checked_add(n)
+        .ok_or_else(|| format_err!("Address range {}+{} is too large", addr, n))
+}
+
+/// Print outputs as text if they are all ASCII, as numbers otherwise.
+fn render_outputs(outputs: &[i64]) {
+    if outputs.is_empty() {
+        return;
+    }
+
+    if outputs.iter().all(|&o| o > 0 && o < 128) {
+        let s: String = outputs.iter().map(|&o| (o as u8) as char).collect();
+        print!("{}", s);
+        if !s.ends_with('\n') {
+            println!();
+        }
+    } else {
+        println!("OUT {:?}", outputs);
+    }
+}
+
+fn show_current(dbg: &Debugger) {
+    let bp = if dbg.breakpoints.contains(&dbg.state.ic) {
+        "*"
+    } else {
+        " "
+    };
+    println!(
+        "{}{:5}: {}    (rb={}, {} inputs queued)",
+        bp,
+        dbg.state.ic,
+        dbg.current_instruction(),
+        dbg.state.relative_base,
+        dbg.state.inputs.len()
+    );
+}
+
+fn show_reason(reason: &StopReason) {
+    match reason {
+        StopReason::Stepped => {}
+        StopReason::Breakpoint(a) => println!("Breakpoint at {}", a),
+        StopReason::Condition => println!("Condition reached"),
+        StopReason::Input => println!("Waiting for input"),
+        StopReason::Output => println!("Produced output"),
+        StopReason::Halt => println!("Halted"),
+        StopReason::Watch(access) => println!("Watchpoint hit at {}", access),
+        StopReason::InfiniteLoop => println!("Stuck in an infinite loop"),
+    }
+}
+
+fn command(dbg: &mut Debugger, line: &str) -> Result<bool> {
+    let mut words = line.split_whitespace();
+    let cmd = match words.next() {
+        Some(c) => c,
+        None => return Ok(true),
+    };
+
+    match cmd {
+        "s" => {
+            let n: usize = match words.next() {
+                Some(n) => parse_num(Some(n), "step count")?,
+                None => 1,
+            };
+
+            let mut reason = StopReason::Stepped;
+            for _ in 0..n {
+                reason = dbg.step()?;
+                if let StopReason::Input | StopReason::Halt | StopReason::InfiniteLoop = reason {
+                    break;
+                }
+            }
+            render_outputs(&dbg.take_outputs());
+            show_reason(&reason);
+        }
+        "rs" => {
+            let n: usize = match words.next() {
+                Some(n) => parse_num(Some(n), "step count")?,
+                None => 1,
+            };
+
+            let mut undone = 0;
+            while undone < n && dbg.step_back().is_some() {
+                undone += 1;
+            }
+            println!("Stepped back {} instructions", undone);
+        }
+        "rio" => loop {
+            match dbg.step_back() {
+                Some(u) if u.is_io() => {
+                    println!("Undid input {:?} / output {:?}", u.input, u.output);
+                    break;
+                }
+                Some(_) => {}
+                None => {
+                    println!("No more history");
+                    break;
+                }
+            }
+        },
+        "c" => {
+            let reason = dbg.cont()?;
+            render_outputs(&dbg.take_outputs());
+            show_reason(&reason);
+        }
+        "u" => {
+            let addr: usize = parse_num(words.next(), "address")?;
+            let reason = dbg.run_until(|s| s.ic == addr)?;
+            render_outputs(&dbg.take_outputs());
+            show_reason(&reason);
+        }
+        "b" => {
+            let addr = parse_num(words.next(), "address")?;
+            dbg.add_breakpoint(addr);
+        }
+        "d" => {
+            let addr = parse_num(words.next(), "address")?;
+            if !dbg.remove_breakpoint(addr) {
+                println!("No breakpoint at {}", addr);
+            }
+        }
+        "bl" => println!("Breakpoints: {:?}", dbg.breakpoints),
+        "wr" | "ww" => {
+            let addr: usize = parse_num(words.next(), "address")?;
+            let n = match words.next() {
+                Some(n) => parse_num(Some(n), "count")?,
+                None => 1,
+            };
+            dbg.state
+                .watch(addr..range_end(addr, n)?, cmd == "wr", cmd == "ww");
+        }
+        "wl" => {
+            for w in dbg.state.watchpoints.iter() {
+                println!(
+                    "{:?}{}{}",
+                    w.addresses,
+                    if w.on_read { " read" } else { "" },
+                    if w.on_write { " write" } else { "" }
+                );
+            }
+        }
+        "wc" => dbg.state.watchpoints.clear(),
+        "l" => {
+            let mut addr = match words.next() {
+                Some(a) => parse_num(Some(a), "address")?,
+                None => dbg.state.ic,
+            };
+            let n = match words.next() {
+                Some(n) => parse_num(Some(n), "count")?,
+                None => 10,
+            };
+
+            for _ in 0..n {
+                if addr >= dbg.state.memory.len() {
+                    break;
+                }
+                let instr = decode(&dbg.state.memory, addr);
+                let marker = if addr == dbg.state.ic { ">" } else { " " };
+                let bp = if dbg.breakpoints.contains(&addr) {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{}{}{:5}: {}", marker, bp, addr, instr);
+                addr += instr.size();
+            }
+        }
+        "x" => {
+            let addr: usize = parse_num(words.next(), "address")?;
+            let n = match words.next() {
+                Some(n) => parse_num(Some(n), "count")?,
+                None => 1,
+            };
+
+            let values: Vec<i64> = (addr..range_end(addr, n)?).map(|a| dbg.peek(a)).collect();
+            println!("{:5}: {:?}", addr, values);
+        }
+        "w" => {
+            let addr = parse_num(words.next(), "address")?;
+            let val = parse_num(words.next(), "value")?;
+            dbg.poke(addr, val);
+        }
+        "ic" => dbg.state.ic = parse_num(words.next(), "address")?,
+        "rb" => dbg.state.relative_base = parse_num(words.next(), "value")?,
+        "i" => {
+            for w in words {
+                dbg.push_input(parse_num(Some(w), "input")?);
+            }
+        }
+        "a" => {
+            let text = line.trim_start()[1..].trim();
+            dbg.push_ascii_line(text);
+        }
+        "iq" => println!("Inputs: {:?}", dbg.state.inputs),
+        "iqc" => dbg.state.inputs.clear(),
+        "r" => println!(
+            "ic={} rb={} memory={} words",
+            dbg.state.ic,
+            dbg.state.relative_base,
+            dbg.state.memory.len()
+        ),
+        "o" => {
+            dbg.stop_on_output = !dbg.stop_on_output;
+            println!("Stop on output: {}", dbg.stop_on_output);
+        }
+        "save" => {
+            let path = words.next().ok_or_else(|| format_err!("Missing path"))?;
+            dbg.state.save_snapshot(path)?;
+        }
+        "load" => {
+            let path = words.next().ok_or_else(|| format_err!("Missing path"))?;
+            dbg.state = State::load_snapshot(path)?;
+            dbg.state.enable_history(Some(HISTORY_LIMIT));
+        }
+        "h" => print!("{}", HELP),
+        "q" => return Ok(false),
+        _ => println!("Unknown command '{}', try 'h'", cmd),
+    }
+
+    Ok(true)
+}
+
+fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| format_err!("Usage: debugger <program file>"))?;
+
+    let program = parse_program(&read_to_string(&path)?)?;
+    let mut dbg = Debugger::new(State::new(program));
+    dbg.state.enable_history(Some(HISTORY_LIMIT));
+
+    let stdin = stdin();
+    let mut lines = stdin.lock().lines();
+
+    loop {
+        show_current(&dbg);
+        print!("(icdb) ");
+        stdout().flush()?;
+
+        let line = match lines.next() {
+            Some(l) => l?,
+            None => break,
+        };
+
+        match command(&mut dbg, &line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,16 @@
+use aoc2019::intcode::decompile::decompile;
+use aoc2019::intcode::parse_program;
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+
+fn main() -> Result<()> {
+    let path = std::env::args()
+        .nth(1)
+        .ok_or_else(|| format_err!("Usage: decompile <program file>"))?;
+
+    let program = parse_program(&read_to_string(&path)?)?;
+
+    print!("{}", decompile(&program));
+
+    Ok(())
+}
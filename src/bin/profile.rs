@@ -0,0 +1,39 @@
+use aoc2019::intcode::{parse_program, IntCodeResult, State};
+use aoc2019::result::{format_err, Result};
+use aoc2019::util::read_to_string;
+
+const HOT_SPOTS: usize = 20;
+
+fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let path = args
+        .next()
+        .ok_or_else(|| format_err!("Usage: profile <program file> [comma-separated inputs]"))?;
+
+    let program = parse_program(&read_to_string(&path)?)?;
+    let mut state = State::new(program);
+    if let Some(inputs) = args.next() {
+        state.inputs = parse_program(&inputs)?.into();
+    }
+
+    state.enable_profiling();
+
+    let mut outputs = Vec::new();
+    loop {
+        match state.run(&mut outputs)? {
+            IntCodeResult::Halt => break,
+            IntCodeResult::Input => {
+                println!("Stopped waiting for input");
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    println!("Outputs: {:?}\n", outputs);
+    if let Some(report) = state.profile_report(HOT_SPOTS, true) {
+        print!("{}", report);
+    }
+
+    Ok(())
+}
@@ -1,72 +1,304 @@
-use crate::result::{format_err, Result};
+use crate::result::Result;
+use std::collections::{BTreeMap, VecDeque};
+use std::ops::Range;
 
+pub mod aot;
+pub mod ascii;
+pub mod asm;
+pub mod cfg;
+pub mod coverage;
+pub mod debugger;
+pub mod decompile;
+pub mod disasm;
+pub mod error;
+pub mod explore;
+pub mod extension;
+pub mod history;
+pub mod io;
+pub mod memory;
+pub mod network;
+pub mod profile;
+pub mod runner;
+pub mod snapshot;
+pub mod topology;
+pub mod trace;
+pub mod watch;
+pub mod watchdog;
+pub mod word;
+
+use coverage::Coverage;
+use disasm::Decoded;
+use error::{ErrorKind, IntCodeError};
+use extension::{Extension, Param};
+use history::History;
+use memory::Memory;
+use profile::Profile;
+use trace::Trace;
+use watch::{AccessKind, MemoryAccess, Watchpoint};
+use watchdog::LoopDetector;
+use word::Word;
+
+/// An Intcode machine, generic over the backend storing its memory, and thereby over the type
+/// of its words.
 #[derive(Debug)]
-pub struct State {
-    pub memory: Vec<i64>,
+pub struct State<M: Memory = Vec<i64>> {
+    pub memory: M,
     pub ic: usize,
-    pub inputs: Vec<i64>,
+    pub inputs: VecDeque<M::Word>,
     pub relative_base: i64,
+    pub watchpoints: Vec<Watchpoint>,
+    pub trace: Option<Trace<M::Word>>,
+    pub history: Option<History<M::Word>>,
+    /// Number of instructions executed so far.
+    pub steps: usize,
+    /// Fail with `ErrorKind::StepLimitExceeded` once this many instructions were executed.
+    pub max_steps: Option<usize>,
+    /// Fail with `ErrorKind::MemoryLimitExceeded` when accessing an address at or beyond this.
+    pub max_memory: Option<usize>,
+    /// Return `IntCodeResult::BudgetExhausted` from `run` after executing this many instructions.
+    pub run_budget: Option<usize>,
+    pub loop_detector: Option<LoopDetector>,
+    pub profile: Option<Profile>,
+    pub coverage: Option<Coverage>,
+    /// Handlers for additional opcodes, see `register_opcode`.
+    pub extensions: BTreeMap<i64, Extension<M::Word>>,
+
+    watch_hits: VecDeque<MemoryAccess<M::Word>>,
 }
 
-pub enum IntCodeResult {
+type ExecResult<T> = std::result::Result<T, IntCodeError>;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum IntCodeResult<W: Word = i64> {
     Input,
     Output,
     Halt,
+    /// A watched memory cell was accessed by the last instruction.
+    Watch(MemoryAccess<W>),
+    /// The instruction budget for a single `run` was used up.
+    BudgetExhausted,
+    /// The machine returned to an earlier state without consuming input, and will loop forever.
+    InfiniteLoop,
 }
 
 impl State {
     pub fn new(program: Vec<i64>) -> Self {
+        State::with_memory(program)
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Create a machine using `memory` as its memory backend, e.g. a
+    /// `memory::PagedMemory` for programs that access very high addresses.
+    pub fn with_memory(memory: M) -> Self {
         State {
-            memory: program,
+            memory,
             ic: 0,
-            inputs: Vec::new(),
+            inputs: VecDeque::new(),
             relative_base: 0,
+            watchpoints: Vec::new(),
+            trace: None,
+            history: None,
+            steps: 0,
+            max_steps: None,
+            max_memory: None,
+            run_budget: None,
+            loop_detector: None,
+            profile: None,
+            coverage: None,
+            extensions: BTreeMap::new(),
+            watch_hits: VecDeque::new(),
         }
     }
 
-    fn get_memory(&mut self, address: usize) -> i64 {
-        while self.memory.len() <= address {
-            self.memory.push(0);
+    /// Reload memory from `program` and reset the registers, inputs and step count, to run the
+    /// program again from the start.
+    ///
+    /// Watchpoints, limits, extensions, the profile and coverage are kept,
+    /// while the undo history is cleared, loop detection starts over and trace recording is
+    /// stopped.
+    pub fn restart(&mut self, program: &[M::Word]) {
+        self.memory = M::from_program(program.to_vec());
+        self.ic = 0;
+        self.inputs.clear();
+        self.relative_base = 0;
+        self.steps = 0;
+        self.watch_hits.clear();
+        self.trace = None;
+        if let Some(history) = self.history.as_mut() {
+            *history = History::new(history.limit);
+        }
+        if self.loop_detector.is_some() {
+            self.enable_loop_detection();
         }
+    }
 
-        self.memory[address]
+    /// Suspend execution with `IntCodeResult::Watch` after an instruction reads from and/or
+    /// writes to an address in `addresses`.
+    pub fn watch(&mut self, addresses: Range<usize>, on_read: bool, on_write: bool) {
+        self.watchpoints.push(Watchpoint {
+            addresses,
+            on_read,
+            on_write,
+        });
     }
 
-    fn set_memory(&mut self, address: usize, value: i64) {
-        while self.memory.len() <= address {
-            self.memory.push(0);
+    fn check_watchpoints(
+        &mut self,
+        kind: AccessKind,
+        address: usize,
+        old_value: &M::Word,
+        new_value: &M::Word,
+    ) {
+        if self.watchpoints.iter().any(|w| w.matches(kind, address)) {
+            self.watch_hits.push_back(MemoryAccess {
+                kind,
+                ic: self.ic,
+                address,
+                old_value: old_value.clone(),
+                new_value: new_value.clone(),
+            });
         }
+    }
 
-        self.memory[address] = value;
+    fn error(&self, kind: ErrorKind) -> IntCodeError {
+        IntCodeError {
+            kind,
+            ic: self.ic,
+            instruction: self
+                .memory
+                .fetch(self.ic)
+                .and_then(|w| w.to_i64())
+                .unwrap_or(0),
+            relative_base: self.relative_base,
+        }
     }
 
-    fn get_address(&mut self, parmodes: &Vec<u8>, ofs: usize) -> Result<usize> {
-        let pm = parmodes.get(ofs - 1).unwrap_or(&0);
-        let pv = self.memory[self.ic + ofs];
+    fn check_address(&self, address: i64) -> ExecResult<usize> {
+        if address < 0 {
+            return Err(self.error(ErrorKind::NegativeAddress(address)));
+        }
+
+        let address = address as usize;
+        match self.max_memory {
+            Some(max) if address >= max => Err(self.error(ErrorKind::MemoryLimitExceeded(address))),
+            _ => Ok(address),
+        }
+    }
+
+    /// Convert a word used as an address, jump target or offset.
+    fn to_i64(&self, word: &M::Word) -> ExecResult<i64> {
+        word.to_i64()
+            .ok_or_else(|| self.error(ErrorKind::WordOutOfRange))
+    }
+
+    /// Add an offset to the relative base.
+    fn offset(&self, offset: i64) -> ExecResult<i64> {
+        self.relative_base
+            .checked_add(offset)
+            .ok_or_else(|| self.error(ErrorKind::Overflow))
+    }
+
+    fn check_jump(&self, target: &M::Word) -> ExecResult<usize> {
+        let target = self.to_i64(target)?;
+        if target < 0 || target as usize >= self.memory.len() {
+            return Err(self.error(ErrorKind::JumpOutOfRange(target)));
+        }
+
+        Ok(target as usize)
+    }
+
+    /// Read a word of the current instruction, which must lie within memory.
+    fn fetch(&self, address: usize) -> ExecResult<M::Word> {
+        match self.memory.fetch(address) {
+            Some(v) => Ok(v),
+            None => Err(self.error(ErrorKind::FetchOutOfRange(address))),
+        }
+    }
+
+    fn get_memory(&mut self, address: usize) -> M::Word {
+        let value = self.memory.read(address);
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_read(address);
+        }
+        self.check_watchpoints(AccessKind::Read, address, &value, &value);
+
+        value
+    }
+
+    fn set_memory(&mut self, address: usize, value: M::Word) {
+        if let Some(profile) = self.profile.as_mut() {
+            profile.record_write(address);
+        }
+        let old_value = self.store(address, value.clone());
+        self.check_watchpoints(AccessKind::Write, address, &old_value, &value);
+    }
+
+    /// Write a memory cell, keeping the loop detector, trace and undo history up to date. Returns the previous value.
+    fn store(&mut self, address: usize, value: M::Word) -> M::Word {
+        let old_value = self.memory.read(address);
+        self.memory.write(address, value.clone());
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.record_write(address, &old_value, &value);
+        }
+
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record_write(address, value);
+        }
+        if let Some(history) = self.history.as_mut() {
+            history.record_write(address, old_value.clone());
+        }
+        old_value
+    }
+
+    /// Write a memory cell from outside the program, e.g. from a debugger.
+    ///
+    /// The write is recorded in the trace, and in the undo history as an entry of its own so
+    /// that it can be stepped back. It does not trigger watchpoints and is not profiled.
+    pub fn poke(&mut self, address: usize, value: M::Word) {
+        self.history_begin();
+        self.store(address, value.clone());
+        if let Some(history) = self.history.as_mut() {
+            history.finish();
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record_poke(address, value);
+        }
+    }
+
+    fn get_address(&mut self, pm: u8, ofs: usize) -> ExecResult<usize> {
+        let pv = self.fetch(self.ic + ofs)?;
+        let pv = self.to_i64(&pv)?;
 
         let addr = match pm {
             0 => {
                 // position mode
-                pv as usize
+                pv
             }
+            1 => return Err(self.error(ErrorKind::WriteInImmediateMode { param: ofs })),
             2 => {
                 // relative mode
-                (self.relative_base + pv) as usize
+                self.offset(pv)?
+            }
+            _ => {
+                return Err(self.error(ErrorKind::InvalidParameterMode {
+                    param: ofs,
+                    mode: pm,
+                }))
             }
-            _ => return Err(format_err!("Invalid parameter mode at {}: {}", ofs, pm)),
         };
 
-        Ok(addr)
+        self.check_address(addr)
     }
 
-    fn get_parameter(&mut self, parmodes: &Vec<u8>, ofs: usize) -> Result<i64> {
-        let pm = parmodes.get(ofs - 1).unwrap_or(&0);
-        let pv = self.memory[self.ic + ofs];
+    fn get_parameter(&mut self, pm: u8, ofs: usize) -> ExecResult<M::Word> {
+        let pv = self.fetch(self.ic + ofs)?;
 
         let val = match pm {
             0 => {
                 // position mode
-                self.get_memory(pv as usize)
+                let addr = self.check_address(self.to_i64(&pv)?)?;
+                self.get_memory(addr)
             }
             1 => {
                 // immediate mode
@@ -74,115 +306,289 @@ impl State {
             }
             2 => {
                 // relative mode
-                self.get_memory((self.relative_base + pv) as usize)
+                let addr = self.offset(self.to_i64(&pv)?)?;
+                let addr = self.check_address(addr)?;
+                self.get_memory(addr)
+            }
+            _ => {
+                return Err(self.error(ErrorKind::InvalidParameterMode {
+                    param: ofs,
+                    mode: pm,
+                }))
             }
-            _ => return Err(format_err!("Invalid parameter mode at {}: {}", ofs, pm)),
         };
 
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record_value(val.clone());
+        }
+
         Ok(val)
     }
 
-    fn get_opcode_and_parmode(&self) -> (i64, Vec<u8>) {
-        let mut v = self.memory[self.ic];
+    fn decode(&self) -> ExecResult<Decoded> {
+        let word = self.fetch(self.ic)?;
+        Ok(Decoded::new(self.to_i64(&word)?))
+    }
+
+    /// Execute a single instruction.
+    ///
+    /// Returns `None` if execution can continue with the next instruction, or the reason for
+    /// stopping otherwise. Waiting for input does not advance the instruction counter.
+    ///
+    /// Watchpoint hits are reported once the accessing instruction has completed, one per call.
+    pub fn step(
+        &mut self,
+        outputs: &mut Vec<M::Word>,
+    ) -> ExecResult<Option<IntCodeResult<M::Word>>> {
+        if let Some(access) = self.watch_hits.pop_front() {
+            return Ok(Some(IntCodeResult::Watch(access)));
+        }
 
-        let opcode = v % 100;
-        v /= 100;
+        if let Some(limit) = self.max_steps {
+            if self.steps >= limit {
+                return Err(self.error(ErrorKind::StepLimitExceeded(limit)));
+            }
+        }
 
-        let mut parmodes = Vec::new();
+        // the instruction may overwrite itself, so its opcode is read up front
+        let ic = self.ic;
+        let profiled_opcode = match self.profile {
+            Some(_) => self.memory.read(ic).to_i64().map(|w| w % 100),
+            None => None,
+        };
+
+        self.trace_begin();
+        self.history_begin();
+        let res = self.execute(outputs)?;
 
-        while v > 0 {
-            let pm = v % 10;
-            parmodes.push(pm as u8);
-            v /= 10;
+        let executed = res != Some(IntCodeResult::Input);
+        if executed {
+            self.steps += 1;
         }
 
-        return (opcode, parmodes);
+        if executed {
+            if let (Some(profile), Some(opcode)) = (self.profile.as_mut(), profiled_opcode) {
+                profile.record_execution(ic, opcode);
+            }
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record_execution(ic);
+            }
+        }
+
+        let (ic, relative_base) = (self.ic, self.relative_base);
+        let looping = match self.loop_detector.as_mut() {
+            Some(detector) if executed && res != Some(IntCodeResult::Halt) => {
+                detector.check(ic, relative_base)
+            }
+            _ => false,
+        };
+
+        if let Some(trace) = self.trace.as_mut() {
+            if executed {
+                trace.finish();
+            } else {
+                trace.abort();
+            }
+        }
+        if let Some(history) = self.history.as_mut() {
+            if executed {
+                history.finish();
+            } else {
+                history.abort();
+            }
+        }
+
+        if looping {
+            return Ok(Some(IntCodeResult::InfiniteLoop));
+        }
+
+        Ok(res.or_else(|| self.watch_hits.pop_front().map(IntCodeResult::Watch)))
     }
 
-    pub fn run(&mut self, outputs: &mut Vec<i64>) -> Result<IntCodeResult> {
-        loop {
-            let (opcode, parmodes) = self.get_opcode_and_parmode();
+    fn execute(
+        &mut self,
+        outputs: &mut Vec<M::Word>,
+    ) -> ExecResult<Option<IntCodeResult<M::Word>>> {
+        let Decoded { opcode, modes } = self.decode()?;
 
-            match opcode {
-                1 => {
-                    // add
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
-                    let pos_store = self.get_address(&parmodes, 3)?;
+        match opcode {
+            1 => {
+                // add
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
+                let pos_store = self.get_address(modes[2], 3)?;
+
+                let sum = a
+                    .try_add(&b)
+                    .ok_or_else(|| self.error(ErrorKind::Overflow))?;
+                self.set_memory(pos_store, sum);
+                self.ic += 4;
+            }
+            2 => {
+                // mul
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
+                let pos_store = self.get_address(modes[2], 3)?;
 
-                    self.set_memory(pos_store, a + b);
-                    self.ic += 4;
+                let product = a
+                    .try_mul(&b)
+                    .ok_or_else(|| self.error(ErrorKind::Overflow))?;
+                self.set_memory(pos_store, product);
+                self.ic += 4;
+            }
+            3 => {
+                // input
+                if !self.inputs.is_empty() {
+                    let pos_store = self.get_address(modes[0], 1)?;
+                    let input_val = self.inputs.pop_front().unwrap();
+                    if let Some(trace) = self.trace.as_mut() {
+                        trace.record_input(input_val.clone());
+                    }
+                    if let Some(history) = self.history.as_mut() {
+                        history.record_input(input_val.clone());
+                    }
+                    if let Some(detector) = self.loop_detector.as_mut() {
+                        detector.reset();
+                    }
+                    if let Some(profile) = self.profile.as_mut() {
+                        profile.record_input();
+                    }
+                    self.set_memory(pos_store, input_val);
+                    self.ic += 2;
+                } else {
+                    return Ok(Some(IntCodeResult::Input));
                 }
-                2 => {
-                    // mul
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
-                    let pos_store = self.get_address(&parmodes, 3)?;
-
-                    self.set_memory(pos_store, a * b);
-                    self.ic += 4;
+            }
+            4 => {
+                // output
+                let v = self.get_parameter(modes[0], 1)?;
+                if let Some(trace) = self.trace.as_mut() {
+                    trace.record_output(v.clone());
                 }
-                3 => {
-                    // input
-                    if self.inputs.len() > 0 {
-                        let pos_store = self.get_address(&parmodes, 1)? as usize;
-                        let input_val = self.inputs.remove(0);
-                        self.set_memory(pos_store, input_val);
-                        self.ic += 2;
-                    } else {
-                        return Ok(IntCodeResult::Input);
-                    }
+                if let Some(history) = self.history.as_mut() {
+                    history.record_output(v.clone());
                 }
-                4 => {
-                    // output
-                    let v = self.get_parameter(&parmodes, 1)?;
-                    outputs.push(v);
-                    self.ic += 2;
-                    return Ok(IntCodeResult::Output);
+                if let Some(detector) = self.loop_detector.as_mut() {
+                    detector.reset();
                 }
-                5 => {
-                    // jump-if-true
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
-
-                    self.ic = if a != 0 { b as usize } else { self.ic + 3 };
+                if let Some(profile) = self.profile.as_mut() {
+                    profile.record_output();
                 }
-                6 => {
-                    // jump-if false
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
+                outputs.push(v);
+                self.ic += 2;
+                return Ok(Some(IntCodeResult::Output));
+            }
+            5 => {
+                // jump-if-true
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
 
-                    self.ic = if a == 0 { b as usize } else { self.ic + 3 };
-                }
-                7 => {
-                    // less-than
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
-                    let pos_store = self.get_address(&parmodes, 3)?;
-
-                    self.set_memory(pos_store, if a < b { 1 } else { 0 });
-                    self.ic += 4;
+                if let Some(coverage) = self.coverage.as_mut() {
+                    coverage.record_branch(self.ic, !a.is_zero());
                 }
-                8 => {
-                    // equals
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    let b = self.get_parameter(&parmodes, 2)?;
-                    let pos_store = self.get_address(&parmodes, 3)?;
-
-                    self.set_memory(pos_store, if a == b { 1 } else { 0 });
-                    self.ic += 4;
+
+                self.ic = if !a.is_zero() {
+                    self.check_jump(&b)?
+                } else {
+                    self.ic + 3
+                };
+            }
+            6 => {
+                // jump-if false
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
+
+                if let Some(coverage) = self.coverage.as_mut() {
+                    coverage.record_branch(self.ic, a.is_zero());
                 }
-                9 => {
-                    // shift relative base
-                    let a = self.get_parameter(&parmodes, 1)?;
-                    self.relative_base += a;
-                    self.ic += 2;
+
+                self.ic = if a.is_zero() {
+                    self.check_jump(&b)?
+                } else {
+                    self.ic + 3
+                };
+            }
+            7 => {
+                // less-than
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
+                let pos_store = self.get_address(modes[2], 3)?;
+
+                self.set_memory(pos_store, M::Word::from_i64(if a < b { 1 } else { 0 }));
+                self.ic += 4;
+            }
+            8 => {
+                // equals
+                let a = self.get_parameter(modes[0], 1)?;
+                let b = self.get_parameter(modes[1], 2)?;
+                let pos_store = self.get_address(modes[2], 3)?;
+
+                self.set_memory(pos_store, M::Word::from_i64(if a == b { 1 } else { 0 }));
+                self.ic += 4;
+            }
+            9 => {
+                // shift relative base
+                let a = self.get_parameter(modes[0], 1)?;
+                self.relative_base = self.offset(self.to_i64(&a)?)?;
+                self.ic += 2;
+            }
+            99 => {
+                // halt
+                return Ok(Some(IntCodeResult::Halt));
+            }
+            _ => match self.extensions.get(&opcode).cloned() {
+                Some(extension) => self.execute_extension(opcode, &extension, modes)?,
+                None => return Err(self.error(ErrorKind::InvalidOpcode(opcode))),
+            },
+        }
+
+        Ok(None)
+    }
+
+    fn execute_extension(
+        &mut self,
+        opcode: i64,
+        extension: &Extension<M::Word>,
+        modes: [u8; 3],
+    ) -> ExecResult<()> {
+        let mut args = Vec::with_capacity(extension.params.len());
+        let mut targets = Vec::new();
+        for (i, param) in extension.params.iter().enumerate() {
+            match param {
+                Param::Read => args.push(self.get_parameter(modes[i], i + 1)?),
+                Param::Write => {
+                    targets.push((i, self.get_address(modes[i], i + 1)?));
+                    args.push(M::Word::from_i64(0));
                 }
-                99 => {
-                    // halt
-                    return Ok(IntCodeResult::Halt);
+            }
+        }
+
+        (extension.handler)(&mut args).map_err(|e| {
+            self.error(ErrorKind::ExtensionFailed {
+                opcode,
+                message: e.to_string(),
+            })
+        })?;
+
+        for (i, address) in targets {
+            self.set_memory(address, args[i].clone());
+        }
+        self.ic += 1 + extension.params.len();
+        Ok(())
+    }
+
+    /// Run until the program halts, produces an output or waits for input.
+    pub fn run(&mut self, outputs: &mut Vec<M::Word>) -> ExecResult<IntCodeResult<M::Word>> {
+        let start = self.steps;
+        loop {
+            if let Some(budget) = self.run_budget {
+                if self.steps - start >= budget {
+                    return Ok(IntCodeResult::BudgetExhausted);
                 }
-                _ => return Err(format_err!("Invalid opcode: {}", opcode)),
+            }
+
+            if let Some(res) = self.step(outputs)? {
+                return Ok(res);
             }
         }
     }
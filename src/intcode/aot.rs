@@ -0,0 +1,615 @@
+//! Ahead-of-time translation of Intcode programs into Rust source.
+//!
+//! The generated module defines a `Machine` with the same `inputs` queue and `run` method as
+//! `State`. Every instruction that is statically reachable from address 0 becomes an arm of a
+//! `match` on the instruction counter. Reachability follows immediate jump targets, as well as
+//! immediate `ADD` and `MUL` operands that point at a valid instruction, which is how compiled
+//! Intcode pushes return addresses.
+//!
+//! Operand words that are the target of a position-mode write, such as patched return
+//! addresses, are read from memory at run time. Otherwise, the translation assumes that the
+//! program does not modify its own code. As soon as an instruction is about to write into the
+//! rest of the code region, jumps to an address that was not translated, or would fail, e.g.
+//! because its arithmetic overflows, the machine hands its memory and registers to a `State` and
+//! interprets the rest of the program, starting with that instruction, so the interpreter's
+//! behavior and errors are preserved.
+
+use super::disasm::{decode, Instruction, Mode, Opcode, Operand};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Find the instructions reachable from address 0.
+pub fn reachable(program: &[i64]) -> BTreeMap<usize, Instruction> {
+    let mut out = BTreeMap::new();
+    let mut todo = vec![0];
+
+    let push_target = |todo: &mut Vec<usize>, value: i64| {
+        if value >= 0 && (value as usize) < program.len() {
+            todo.push(value as usize);
+        }
+    };
+
+    while let Some(address) = todo.pop() {
+        if out.contains_key(&address) {
+            continue;
+        }
+
+        let instruction = decode(program, address);
+        let (opcode, operands) = match &instruction {
+            Instruction::Op { opcode, operands } => (*opcode, operands),
+            Instruction::Data(_) => continue,
+        };
+
+        match opcode {
+            Opcode::Halt => {}
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+                if operands[1].mode == Mode::Immediate {
+                    push_target(&mut todo, operands[1].value);
+                }
+
+                // an immediate condition makes one of the branches unreachable
+                let cond = operands[0];
+                let jumps_always = match opcode {
+                    Opcode::JumpIfTrue => cond.value != 0,
+                    _ => cond.value == 0,
+                };
+                if cond.mode != Mode::Immediate || !jumps_always {
+                    todo.push(address + 3);
+                }
+            }
+            Opcode::Add | Opcode::Mul => {
+                for op in &operands[..2] {
+                    if op.mode == Mode::Immediate {
+                        push_target(&mut todo, op.value);
+                    }
+                }
+                todo.push(address + 4);
+            }
+            _ => todo.push(address + instruction.size()),
+        }
+
+        out.insert(address, instruction);
+    }
+
+    out
+}
+
+/// Translate `program` into a Rust module, referring to the interpreter as `intcode_path`, e.g.
+/// `aoc2019::intcode`.
+pub fn transpile(program: &[i64], intcode_path: &str) -> String {
+    let instructions = reachable(program);
+    let code = Code::new(&instructions);
+
+    let mut out = String::new();
+    let w = &mut out;
+
+    emit_header(w, program, intcode_path);
+
+    for (address, instruction) in &instructions {
+        if let Instruction::Op { opcode, operands } = instruction {
+            emit_instruction(w, &code, *address, *opcode, operands);
+        }
+    }
+
+    emit_footer(w, &code);
+
+    out
+}
+
+fn emit_header(w: &mut String, program: &[i64], intcode_path: &str) {
+    let words: Vec<String> = program.iter().map(|v| v.to_string()).collect();
+
+    let _ = write!(
+        w,
+        "\
+// Generated by the Intcode ahead-of-time translator. Do not edit.
+#![allow(dead_code, clippy::all)]
+
+use std::collections::VecDeque;
+use {path}::error::IntCodeError;
+use {path}::{{IntCodeResult, State}};
+
+pub const PROGRAM: [i64; {len}] = [{words}];
+
+#[derive(Debug)]
+pub struct Machine {{
+    pub inputs: VecDeque<i64>,
+    mem: Vec<i64>,
+    pc: usize,
+    rb: i64,
+    fallback: Option<State>,
+}}
+
+impl Default for Machine {{
+    fn default() -> Self {{
+        Machine::new()
+    }}
+}}
+
+impl Machine {{
+    pub fn new() -> Self {{
+        Machine {{
+            inputs: VecDeque::new(),
+            mem: PROGRAM.to_vec(),
+            pc: 0,
+            rb: 0,
+            fallback: None,
+        }}
+    }}
+
+    /// Whether execution has been handed over to the interpreter.
+    pub fn is_interpreted(&self) -> bool {{
+        self.fallback.is_some()
+    }}
+
+    /// Run until the program halts, produces an output or waits for input.
+    pub fn run(&mut self, outputs: &mut Vec<i64>) -> Result<IntCodeResult, IntCodeError> {{
+        if let Some(state) = self.fallback.as_mut() {{
+            state.inputs.append(&mut self.inputs);
+            return state.run(outputs);
+        }}
+
+        loop {{
+            match self.pc {{
+",
+        path = intcode_path,
+        len = program.len(),
+        words = words.join(", "),
+    );
+}
+
+/// The words making up the translated instructions.
+struct Code {
+    /// Words that are translated as constants, which must not be written to.
+    fixed: BTreeSet<usize>,
+    /// Operand words that are written by position-mode parameters, and read at run time.
+    patched: BTreeSet<usize>,
+}
+
+impl Code {
+    fn new(instructions: &BTreeMap<usize, Instruction>) -> Self {
+        let mut fixed = BTreeSet::new();
+        let mut targets = BTreeSet::new();
+
+        for (address, instruction) in instructions {
+            fixed.extend(*address..address + instruction.size());
+
+            if let Instruction::Op { opcode, operands } = instruction {
+                for (i, op) in operands.iter().enumerate() {
+                    if opcode.writes_param(i) && op.mode == Mode::Position && op.value >= 0 {
+                        targets.insert(op.value as usize);
+                    }
+                }
+            }
+        }
+
+        let patched: BTreeSet<usize> = targets
+            .into_iter()
+            .filter(|a| fixed.contains(a) && !instructions.contains_key(a))
+            .collect();
+
+        for a in &patched {
+            fixed.remove(a);
+        }
+
+        Code { fixed, patched }
+    }
+
+    /// The value of the operand stored at `address`, as an expression.
+    fn value(&self, address: usize, operand: Operand) -> String {
+        if self.patched.contains(&address) {
+            format!("self.read({})", address)
+        } else {
+            operand.value.to_string()
+        }
+    }
+}
+
+fn emit_footer(w: &mut String, code: &Code) {
+    let mut ranges = Vec::new();
+    for &a in &code.fixed {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == a => *end = a,
+            _ => ranges.push((a, a)),
+        }
+    }
+
+    let ranges: Vec<String> = ranges
+        .into_iter()
+        .map(|(start, end)| format!("{}..={}", start, end))
+        .collect();
+    let ranges = if ranges.is_empty() {
+        "_ if false".to_string()
+    } else {
+        ranges.join(" | ")
+    };
+
+    let _ = write!(
+        w,
+        "\
+                _ => return self.interpret(outputs),
+            }}
+        }}
+    }}
+
+    fn interpret(&mut self, outputs: &mut Vec<i64>) -> Result<IntCodeResult, IntCodeError> {{
+        let mut state = State::new(std::mem::take(&mut self.mem));
+        state.ic = self.pc;
+        state.relative_base = self.rb;
+        state.inputs = std::mem::take(&mut self.inputs);
+
+        let res = state.run(outputs);
+        self.fallback = Some(state);
+        res
+    }}
+
+    fn read(&self, address: usize) -> i64 {{
+        self.mem.get(address).copied().unwrap_or(0)
+    }}
+
+    fn write(&mut self, address: usize, value: i64) {{
+        if self.mem.len() <= address {{
+            self.mem.resize(address + 1, 0);
+        }}
+        self.mem[address] = value;
+    }}
+
+    fn relative(&self, offset: i64) -> Option<usize> {{
+        match self.rb.checked_add(offset) {{
+            Some(address) if address >= 0 => Some(address as usize),
+            _ => None,
+        }}
+    }}
+}}
+
+fn is_code(address: usize) -> bool {{
+    match address {{
+        {} => true,
+        _ => false,
+    }}
+}}
+",
+        ranges
+    );
+}
+
+/// Render operand `i` of the instruction at `address` as an expression, pushing any statements
+/// needed to compute the address it refers to.
+fn read_operand(
+    pre: &mut Vec<String>,
+    code: &Code,
+    address: usize,
+    operand: Operand,
+    i: usize,
+) -> Option<String> {
+    let at = address + 1 + i;
+    let value = code.value(at, operand);
+
+    match operand.mode {
+        Mode::Immediate => Some(value),
+        Mode::Position if code.patched.contains(&at) => {
+            pre.push(format!(
+                "let a{} = match {} {{ a if a >= 0 => a as usize, _ => return self.interpret(outputs) }};",
+                i, value
+            ));
+            Some(format!("self.read(a{})", i))
+        }
+        Mode::Position if operand.value < 0 => None,
+        Mode::Position => Some(format!("self.read({})", value)),
+        Mode::Relative => {
+            pre.push(format!(
+                "let a{} = match self.relative({}) {{ Some(a) => a, None => return self.interpret(outputs) }};",
+                i, value
+            ));
+            Some(format!("self.read(a{})", i))
+        }
+    }
+}
+
+/// Render the address written by operand `i` of the instruction at `address`, pushing
+/// statements that bail out to the interpreter for writes into the code region.
+fn write_address(
+    pre: &mut Vec<String>,
+    code: &Code,
+    address: usize,
+    operand: Operand,
+    i: usize,
+) -> Option<String> {
+    let at = address + 1 + i;
+    let value = code.value(at, operand);
+
+    match operand.mode {
+        Mode::Position if code.patched.contains(&at) => {
+            pre.push(format!(
+                "let a{} = match {} {{ a if a >= 0 && !is_code(a as usize) => a as usize, _ => return self.interpret(outputs) }};",
+                i, value
+            ));
+            Some(format!("a{}", i))
+        }
+        Mode::Position if operand.value < 0 || code.fixed.contains(&(operand.value as usize)) => {
+            None
+        }
+        Mode::Position => Some(value),
+        Mode::Relative => {
+            pre.push(format!(
+                "let a{} = match self.relative({}) {{ Some(a) if !is_code(a) => a, _ => return self.interpret(outputs) }};",
+                i, value
+            ));
+            Some(format!("a{}", i))
+        }
+        Mode::Immediate => None,
+    }
+}
+
+fn emit_instruction(
+    w: &mut String,
+    code: &Code,
+    address: usize,
+    opcode: Opcode,
+    operands: &[Operand],
+) {
+    let mut pre = Vec::new();
+
+    let body = translate(&mut pre, code, address, opcode, operands);
+
+    let _ = writeln!(w, "                {} => {{", address);
+    let _ = writeln!(
+        w,
+        "                    // {}",
+        Instruction::Op {
+            opcode,
+            operands: operands.to_vec()
+        }
+    );
+
+    let lines = match body {
+        Some(body) => pre.into_iter().chain(body).collect(),
+        None => vec!["return self.interpret(outputs);".to_string()],
+    };
+    for line in lines {
+        let _ = writeln!(w, "                    {}", line);
+    }
+
+    let _ = writeln!(w, "                }}");
+}
+
+/// Translate an instruction into statements, or `None` if it must always be interpreted.
+fn translate(
+    pre: &mut Vec<String>,
+    code: &Code,
+    address: usize,
+    opcode: Opcode,
+    operands: &[Operand],
+) -> Option<Vec<String>> {
+    let next = address + 1 + operands.len();
+    let body = match opcode {
+        Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => {
+            let a = read_operand(pre, code, address, operands[0], 0)?;
+            let b = read_operand(pre, code, address, operands[1], 1)?;
+            let dst = write_address(pre, code, address, operands[2], 2)?;
+
+            let value = match opcode {
+                Opcode::Add => format!(
+                    "match i64::checked_add({}, {}) {{ Some(v) => v, None => return self.interpret(outputs) }}",
+                    a, b
+                ),
+                Opcode::Mul => format!(
+                    "match i64::checked_mul({}, {}) {{ Some(v) => v, None => return self.interpret(outputs) }}",
+                    a, b
+                ),
+                Opcode::LessThan => format!("if {} < {} {{ 1 }} else {{ 0 }}", a, b),
+                _ => format!("if {} == {} {{ 1 }} else {{ 0 }}", a, b),
+            };
+
+            vec![
+                format!("let v = {};", value),
+                format!("self.write({}, v);", dst),
+                format!("self.pc = {};", next),
+            ]
+        }
+        Opcode::In => {
+            let dst = write_address(pre, code, address, operands[0], 0)?;
+            vec![
+                "let v = match self.inputs.pop_front() { Some(v) => v, None => return Ok(IntCodeResult::Input) };"
+                    .to_string(),
+                format!("self.write({}, v);", dst),
+                format!("self.pc = {};", next),
+            ]
+        }
+        Opcode::Out => {
+            let a = read_operand(pre, code, address, operands[0], 0)?;
+            vec![
+                format!("outputs.push({});", a),
+                format!("self.pc = {};", next),
+                "return Ok(IntCodeResult::Output);".to_string(),
+            ]
+        }
+        Opcode::JumpIfTrue | Opcode::JumpIfFalse => {
+            let a = read_operand(pre, code, address, operands[0], 0)?;
+            let b = read_operand(pre, code, address, operands[1], 1)?;
+            let cmp = if opcode == Opcode::JumpIfTrue {
+                "!="
+            } else {
+                "=="
+            };
+
+            vec![
+                format!("if {} {} 0 {{", a, cmp),
+                format!("    let t = {};", b),
+                "    if t < 0 || t as usize >= self.mem.len() { return self.interpret(outputs); }"
+                    .to_string(),
+                "    self.pc = t as usize;".to_string(),
+                "} else {".to_string(),
+                format!("    self.pc = {};", next),
+                "}".to_string(),
+            ]
+        }
+        Opcode::AdjustRelativeBase => {
+            let a = read_operand(pre, code, address, operands[0], 0)?;
+            vec![
+                format!(
+                    "self.rb = match self.rb.checked_add({}) {{ Some(rb) => rb, None => return self.interpret(outputs) }};",
+                    a
+                ),
+                format!("self.pc = {};", next),
+            ]
+        }
+        Opcode::Halt => vec!["return Ok(IntCodeResult::Halt);".to_string()],
+    };
+
+    Some(body)
+}
+
+// the translation of `test::SAMPLE`, which `test_sample_is_current` keeps up to date
+#[cfg(test)]
+#[rustfmt::skip]
+#[path = "../../data/aot/sample.rs"]
+mod sample;
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::error::{ErrorKind, IntCodeError};
+    use crate::intcode::{IntCodeResult, State};
+
+    /// Path of the checked-in translation of `SAMPLE`.
+    const SAMPLE_PATH: &str = "data/aot/sample.rs";
+
+    /// A program exercising every instruction and parameter mode, a call through a patched
+    /// return address, a patched operand and every kind of overflow. It reads a mode and a value
+    /// `x`, outputs `x * x` and `x + 1`, and then
+    ///
+    /// * mode 0: doubles `x` and outputs it until that overflows, unless `x` is negative,
+    /// * mode 1: overflows the relative base,
+    /// * mode 2: overflows the address of a relative operand.
+    ///
+    /// Translations are only tested on this program, as they have to be compiled into the
+    /// tests: those of the Intcode puzzle inputs (days 2, 5, 7, 9, 11, 13, 15, 17, 19, 21, 23
+    /// and 25) take up to 190 kB each, too much to check in.
+    const SAMPLE: &str = "
+                ARB #stack
+                IN [mode]
+                IN [x]
+
+                ; output square(x)
+                ADD [x], #0, rb+1
+                ADD #ret, #0, rb+0
+                JT #1, #square
+        ret:    OUT rb+1
+
+                ; output x + 1 through a patched operand
+                ADD [x], #1, [patch+1]
+        patch:  OUT #0
+
+                EQ [mode], #1, [t]
+                JT [t], #rb_overflow
+                EQ [mode], #2, [t]
+                JT [t], #address_overflow
+
+                LT [x], #0, [t]
+                JF [t], #double
+                HLT
+        double: MUL [x], #2, [x]
+                OUT [x]
+                JT #1, #double
+
+        rb_overflow:
+                ARB [max]
+                ARB #1
+                HLT
+
+        address_overflow:
+                ARB [max]
+                OUT rb+1
+                HLT
+
+        square: ARB #2
+                MUL rb-1, rb-1, rb-1
+                ARB #-2
+                JT #1, rb+0
+
+        mode:   data 0
+        x:      data 0
+        t:      data 0
+        max:    data 9223372036854775807
+        stack:  data 0, 0
+        ";
+
+    type Run = (Vec<i64>, Option<IntCodeError>);
+
+    /// Run `program` until it halts or fails.
+    fn interpret(program: &[i64], inputs: &[i64]) -> Run {
+        let mut state = State::new(program.to_vec());
+        state.inputs.extend(inputs);
+
+        let mut outputs = Vec::new();
+        loop {
+            match state.run(&mut outputs) {
+                Ok(IntCodeResult::Halt) => return (outputs, None),
+                Ok(IntCodeResult::Input) => panic!("Not enough input"),
+                Ok(_) => {}
+                Err(e) => return (outputs, Some(e)),
+            }
+        }
+    }
+
+    /// Run the translation of `SAMPLE` until it halts or fails, and compare with the
+    /// interpreter. Returns the run, and whether execution was handed over to the interpreter.
+    fn check(inputs: &[i64]) -> (Run, bool) {
+        let mut machine = sample::Machine::new();
+        machine.inputs.extend(inputs);
+
+        let mut outputs = Vec::new();
+        let error = loop {
+            match machine.run(&mut outputs) {
+                Ok(IntCodeResult::Halt) => break None,
+                Ok(IntCodeResult::Input) => panic!("Not enough input"),
+                Ok(_) => {}
+                Err(e) => break Some(e),
+            }
+        };
+
+        let run: Run = (outputs, error);
+        assert_eq!(interpret(&sample::PROGRAM, inputs), run);
+        (run, machine.is_interpreted())
+    }
+
+    #[test]
+    fn test_sample_is_current() {
+        // set UPDATE_AOT_SAMPLE to regenerate the translation after changing the translator
+        let translation = transpile(&assemble(SAMPLE).unwrap(), "crate::intcode");
+        if std::env::var_os("UPDATE_AOT_SAMPLE").is_some() {
+            std::fs::write(SAMPLE_PATH, &translation).unwrap();
+        }
+
+        let checked_in = std::fs::read_to_string(SAMPLE_PATH).unwrap();
+        assert!(
+            translation == checked_in,
+            "{} is outdated, run the tests with UPDATE_AOT_SAMPLE=1",
+            SAMPLE_PATH
+        );
+    }
+
+    #[test]
+    fn test_same_outputs() {
+        let ((outputs, error), interpreted) = check(&[0, -3]);
+        assert_eq!((vec![9, -2], None, false), (outputs, error, interpreted));
+
+        let ((outputs, error), interpreted) = check(&[0, 5]);
+        assert_eq!(vec![25, 6, 10, 20], outputs[..4].to_vec());
+        assert_eq!(Some(ErrorKind::Overflow), error.map(|e| e.kind));
+        assert!(interpreted);
+    }
+
+    #[test]
+    fn test_overflow() {
+        for mode in 1..=2 {
+            let ((outputs, error), interpreted) = check(&[mode, 7]);
+            assert_eq!(vec![49, 8], outputs);
+            assert_eq!(Some(ErrorKind::Overflow), error.map(|e| e.kind));
+            assert!(interpreted);
+        }
+    }
+}
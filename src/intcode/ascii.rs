@@ -0,0 +1,224 @@
+//! Text based communication with Intcode programs that use ASCII for their input and output,
+//! such as those of days 17, 21 and 25.
+//!
+//! Outputs between 0 and 127 are collected as text, while all other outputs are taken to be
+//! answers, e.g. the amount of space dust on day 17 or the hull damage on day 21.
+//!
+//! Scripts contain one command per line. Everything after a `#` is a comment, and lines that
+//! are empty after removing comments are skipped.
+
+use super::memory::Memory;
+use super::{IntCodeResult, State};
+use crate::result::{format_err, Result};
+use crate::util::read_to_string;
+
+/// The commands of a script, with comments and surrounding whitespace removed.
+pub fn commands(script: &str) -> Vec<&str> {
+    script
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .collect()
+}
+
+/// The outcome of reading text from a machine.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Read {
+    /// The text that was asked for.
+    Text(String),
+    /// The machine stopped before producing it, e.g. as it waits for input or halted.
+    Stopped(IntCodeResult),
+}
+
+/// Talks to a machine in text. Text that was produced but not read yet is lost when the
+/// adapter is dropped.
+#[derive(Debug)]
+pub struct Ascii<'a, M: Memory<Word = i64> = Vec<i64>> {
+    pub state: &'a mut State<M>,
+    /// The non-ASCII outputs produced so far, in order.
+    pub answers: Vec<i64>,
+
+    text: String,
+}
+
+impl<'a, M: Memory<Word = i64>> Ascii<'a, M> {
+    pub fn new(state: &'a mut State<M>) -> Self {
+        Ascii {
+            state,
+            answers: Vec::new(),
+            text: String::new(),
+        }
+    }
+
+    /// Queue `line` as input, followed by a newline. Fails without queueing anything if `line`
+    /// contains characters other than ASCII, as the machine would take them for answers.
+    pub fn send_line(&mut self, line: &str) -> Result<()> {
+        if let Some(c) = line.chars().find(|c| !c.is_ascii()) {
+            return Err(format_err!("Cannot send non-ASCII character {:?}", c));
+        }
+
+        self.push_line(line);
+        Ok(())
+    }
+
+    /// Queue all commands of `script` as input. Fails without queueing anything if a command
+    /// contains characters other than ASCII.
+    pub fn send_script(&mut self, script: &str) -> Result<()> {
+        let commands = commands(script);
+        if let Some(c) = commands
+            .iter()
+            .flat_map(|c| c.chars())
+            .find(|c| !c.is_ascii())
+        {
+            return Err(format_err!("Cannot send non-ASCII character {:?}", c));
+        }
+
+        for command in commands {
+            self.push_line(command);
+        }
+        Ok(())
+    }
+
+    fn push_line(&mut self, line: &str) {
+        self.state.inputs.extend(line.bytes().map(i64::from));
+        self.state.inputs.push_back(10);
+    }
+
+    /// Queue all commands of the script in the file at `path` as input.
+    pub fn send_script_file(&mut self, path: &str) -> Result<()> {
+        self.send_script(&read_to_string(path)?)
+    }
+
+    /// Run until the next output, returning `None`, or until the machine stops for any other
+    /// reason.
+    fn pump(&mut self) -> Result<Option<IntCodeResult>> {
+        let mut outputs = Vec::new();
+        match self.state.run(&mut outputs)? {
+            IntCodeResult::Output => {}
+            res => return Ok(Some(res)),
+        }
+
+        for v in outputs {
+            if (0..=127).contains(&v) {
+                self.text.push(v as u8 as char);
+            } else {
+                self.answers.push(v);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Run until the machine waits for input, halts or stops for any other reason, collecting
+    /// its output.
+    ///
+    /// Like `State::run`, this also returns on `IntCodeResult::Watch` and
+    /// `IntCodeResult::BudgetExhausted`, after which the machine can be resumed by calling it
+    /// again.
+    pub fn run(&mut self) -> Result<IntCodeResult> {
+        loop {
+            if let Some(res) = self.pump()? {
+                return Ok(res);
+            }
+        }
+    }
+
+    /// The next line of output, without its newline. Runs the machine if needed, and returns
+    /// `Read::Stopped` if it stops before completing the line. The partial line is kept,
+    /// so reading can be resumed after e.g. providing input.
+    pub fn read_line(&mut self) -> Result<Read> {
+        loop {
+            if let Some(end) = self.text.find('\n') {
+                let line = self.text.drain(..=end).collect::<String>();
+                return Ok(Read::Text(line[..end].to_owned()));
+            }
+
+            if let Some(res) = self.pump()? {
+                return Ok(Read::Stopped(res));
+            }
+        }
+    }
+
+    /// All output up to and including the next occurrence of `prompt`. Runs the machine if
+    /// needed, and returns `Read::Stopped` if it stops before printing the prompt.
+    pub fn read_until(&mut self, prompt: &str) -> Result<Read> {
+        loop {
+            if let Some(start) = self.text.find(prompt) {
+                return Ok(Read::Text(
+                    self.text.drain(..start + prompt.len()).collect(),
+                ));
+            }
+
+            if let Some(res) = self.pump()? {
+                return Ok(Read::Stopped(res));
+            }
+        }
+    }
+
+    /// Take all output that was not read yet.
+    pub fn take_text(&mut self) -> String {
+        std::mem::take(&mut self.text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::parse_program;
+
+    #[test]
+    fn test_commands() {
+        let script = "
+            # jump if there is a hole ahead
+            NOT A J    # A is ground
+
+            WALK
+        ";
+        assert_eq!(vec!["NOT A J", "WALK"], commands(script));
+    }
+
+    #[test]
+    fn test_day21() {
+        let program = parse_program(&read_to_string("data/day21/input").unwrap()).unwrap();
+        let mut state = State::new(program);
+        let mut ascii = Ascii::new(&mut state);
+
+        assert_eq!(
+            Read::Text("Input instructions:".to_owned()),
+            ascii.read_line().unwrap()
+        );
+        assert_eq!(
+            Read::Stopped(IntCodeResult::Input),
+            ascii.read_line().unwrap()
+        );
+        assert!(ascii.answers.is_empty());
+
+        assert!(ascii.send_line("NOT A J → T").is_err());
+        assert!(ascii.send_script("NOT A J\nWALK ✓").is_err());
+        assert!(ascii.state.inputs.is_empty());
+
+        ascii
+            .send_script(
+                "
+            NOT A J
+            NOT C T
+            AND D T     # only jump with ground to land on
+            OR T J
+            WALK
+            ",
+            )
+            .unwrap();
+        assert_eq!(IntCodeResult::Halt, ascii.run().unwrap());
+        assert_eq!(1, ascii.answers.len());
+        assert!(ascii.answers[0] > 127);
+        match ascii.read_until("Walking...").unwrap() {
+            Read::Text(text) => assert_eq!("Walking...", text.trim()),
+            res => panic!("Unexpected result: {:?}", res),
+        }
+        assert_eq!("", ascii.take_text().trim());
+        assert_eq!(
+            Read::Stopped(IntCodeResult::Halt),
+            ascii.read_line().unwrap()
+        );
+    }
+}
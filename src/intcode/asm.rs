@@ -0,0 +1,580 @@
+//! A small assembler for Intcode.
+//!
+//! The accepted syntax is a superset of the listing produced by `disasm::listing`:
+//!
+//! ```text
+//! ; comments run to the end of the line
+//! const LIMIT = 10            ; named constant
+//!
+//! start:  IN [counter]
+//! loop:   ADD [counter], #-1, [counter]
+//!         OUT [counter]
+//!         JT [counter], #loop
+//!         HLT
+//! counter: data 0
+//! ```
+//!
+//! Operands are written `[addr]` (position mode), `#value` (immediate mode) or `rb+ofs` /
+//! `rb-ofs` (relative mode), where addresses and values may be sums and differences of numbers,
+//! labels and constants. A leading `123:` address as found in listings is checked against the
+//! current address.
+
+use super::disasm::{Instruction, Mode, Opcode, Operand};
+use std::collections::HashMap;
+
+/// An assembly error, pointing at a 1-based line and column in the source.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for AsmError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+type AsmResult<T> = std::result::Result<T, AsmError>;
+
+#[derive(Debug, Clone)]
+enum Term {
+    Number(i64),
+    Symbol { name: String, column: usize },
+}
+
+/// A sum of signed terms, starting at `column`.
+#[derive(Debug, Clone)]
+struct Expr {
+    terms: Vec<(i64, Term)>,
+    column: usize,
+}
+
+#[derive(Debug)]
+enum Item {
+    Op {
+        opcode: Opcode,
+        operands: Vec<(Mode, Expr)>,
+    },
+    Data(Vec<Expr>),
+}
+
+struct Cursor {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Cursor {
+    fn new(src: &str, line: usize) -> Self {
+        Cursor {
+            chars: src.chars().collect(),
+            pos: 0,
+            line,
+        }
+    }
+
+    fn column(&self) -> usize {
+        self.pos + 1
+    }
+
+    fn error<T>(&self, message: String) -> AsmResult<T> {
+        self.error_at(self.column(), message)
+    }
+
+    fn error_at<T>(&self, column: usize, message: String) -> AsmResult<T> {
+        Err(AsmError {
+            line: self.line,
+            column,
+            message,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, ofs: usize) -> Option<char> {
+        self.chars.get(self.pos + ofs).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(c) = self.peek() {
+            if !c.is_whitespace() {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn at_end(&mut self) -> bool {
+        self.skip_whitespace();
+        self.peek().is_none()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, c: char) -> AsmResult<()> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            self.error(format!("Expected '{}'", c))
+        }
+    }
+
+    fn is_ident_start(c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_' || c == '.'
+    }
+
+    fn is_ident_char(c: char) -> bool {
+        c.is_ascii_alphanumeric() || c == '_' || c == '.'
+    }
+
+    /// Read an identifier at the current position, if there is one.
+    fn ident(&mut self) -> Option<String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(c) if Self::is_ident_start(c) => {}
+            _ => return None,
+        }
+
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !Self::is_ident_char(c) {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        Some(self.chars[start..self.pos].iter().collect())
+    }
+
+    /// Read a decimal number at the current position, if there is one. Negative numbers are
+    /// parsed with their sign, so that `i64::MIN` can be written.
+    fn number(&mut self, negative: bool) -> AsmResult<Option<i64>> {
+        self.skip_whitespace();
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if !c.is_ascii_digit() {
+                break;
+            }
+            self.pos += 1;
+        }
+
+        if start == self.pos {
+            return Ok(None);
+        }
+
+        let mut s: String = self.chars[start..self.pos].iter().collect();
+        if negative {
+            s.insert(0, '-');
+        }
+        match s.parse() {
+            Ok(v) => Ok(Some(v)),
+            Err(e) => self.error_at(start + 1, format!("Invalid number '{}': {}", s, e)),
+        }
+    }
+
+    /// Parse a term preceded by `sign`, returning the sign left to apply to it.
+    fn term(&mut self, sign: i64) -> AsmResult<(i64, Term)> {
+        self.skip_whitespace();
+        let column = self.column();
+
+        if let Some(v) = self.number(sign < 0)? {
+            Ok((1, Term::Number(v)))
+        } else if let Some(name) = self.ident() {
+            Ok((sign, Term::Symbol { name, column }))
+        } else {
+            self.error("Expected a number or a symbol".to_owned())
+        }
+    }
+
+    /// Parse the continuation `(('+' | '-') term)*` of an expression.
+    fn expr_tail(&mut self, terms: &mut Vec<(i64, Term)>) -> AsmResult<()> {
+        loop {
+            let sign = if self.eat('+') {
+                1
+            } else if self.eat('-') {
+                -1
+            } else {
+                return Ok(());
+            };
+
+            terms.push(self.term(sign)?);
+        }
+    }
+
+    fn expr(&mut self) -> AsmResult<Expr> {
+        self.skip_whitespace();
+        let column = self.column();
+        let sign = if self.eat('-') {
+            -1
+        } else {
+            self.eat('+');
+            1
+        };
+
+        let mut terms = vec![self.term(sign)?];
+        self.expr_tail(&mut terms)?;
+
+        Ok(Expr { terms, column })
+    }
+
+    /// Check whether the upcoming characters are the relative base marker `rb`.
+    fn at_relative_base(&mut self) -> bool {
+        self.skip_whitespace();
+        let is_rb = self.peek().map(|c| c.to_ascii_lowercase()) == Some('r')
+            && self.peek_at(1).map(|c| c.to_ascii_lowercase()) == Some('b');
+        let followed_by_ident = self.peek_at(2).map(Self::is_ident_char).unwrap_or(false);
+
+        is_rb && !followed_by_ident
+    }
+
+    fn operand(&mut self) -> AsmResult<(Mode, Expr)> {
+        if self.eat('[') {
+            let e = self.expr()?;
+            self.expect(']')?;
+            Ok((Mode::Position, e))
+        } else if self.eat('#') {
+            Ok((Mode::Immediate, self.expr()?))
+        } else if self.at_relative_base() {
+            let column = self.column();
+            self.pos += 2;
+            let mut terms = vec![(1, Term::Number(0))];
+            self.expr_tail(&mut terms)?;
+            Ok((Mode::Relative, Expr { terms, column }))
+        } else {
+            self.error("Expected an operand: [addr], #value or rb+ofs".to_owned())
+        }
+    }
+}
+
+struct Assembler {
+    items: Vec<(usize, Item)>,
+    labels: HashMap<String, usize>,
+    constants: Vec<(String, Expr, usize)>,
+    values: HashMap<String, i64>,
+    address: usize,
+}
+
+impl Assembler {
+    fn new() -> Self {
+        Assembler {
+            items: Vec::new(),
+            labels: HashMap::new(),
+            constants: Vec::new(),
+            values: HashMap::new(),
+            address: 0,
+        }
+    }
+
+    fn is_defined(&self, name: &str) -> bool {
+        self.labels.contains_key(name) || self.constants.iter().any(|(n, _, _)| n == name)
+    }
+
+    fn define_label(&mut self, cur: &Cursor, name: String, column: usize) -> AsmResult<()> {
+        if self.is_defined(&name) {
+            return cur.error_at(column, format!("Duplicate definition of '{}'", name));
+        }
+        self.labels.insert(name, self.address);
+        Ok(())
+    }
+
+    fn parse_line(&mut self, line_no: usize, text: &str) -> AsmResult<()> {
+        let text = match text.find(';') {
+            Some(p) => &text[..p],
+            None => text,
+        };
+
+        let mut cur = Cursor::new(text, line_no);
+
+        // address prefix from a disassembler listing
+        cur.skip_whitespace();
+        let column = cur.column();
+        if let Some(addr) = cur.number(false)? {
+            cur.expect(':')?;
+            if addr as usize != self.address {
+                return cur.error_at(
+                    column,
+                    format!(
+                        "Listing address {} does not match assembled address {}",
+                        addr, self.address
+                    ),
+                );
+            }
+        }
+
+        // labels and the mnemonic
+        let mnemonic = loop {
+            cur.skip_whitespace();
+            let column = cur.column();
+            let name = match cur.ident() {
+                Some(n) => n,
+                None if cur.at_end() => return Ok(()),
+                None => return cur.error("Expected a label or mnemonic".to_owned()),
+            };
+
+            if cur.eat(':') {
+                self.define_label(&cur, name, column)?;
+            } else {
+                break (name, column);
+            }
+        };
+
+        let (name, column) = mnemonic;
+        let lname = name.to_ascii_lowercase();
+
+        if lname == "const" {
+            let column = {
+                cur.skip_whitespace();
+                cur.column()
+            };
+            let cname = match cur.ident() {
+                Some(n) => n,
+                None => return cur.error("Expected a constant name".to_owned()),
+            };
+            if self.is_defined(&cname) {
+                return cur.error_at(column, format!("Duplicate definition of '{}'", cname));
+            }
+            cur.expect('=')?;
+            let e = cur.expr()?;
+            self.constants.push((cname, e, line_no));
+        } else if lname == "data" {
+            let mut values = vec![cur.expr()?];
+            while cur.eat(',') {
+                values.push(cur.expr()?);
+            }
+
+            self.address += values.len();
+            self.items.push((line_no, Item::Data(values)));
+        } else if let Some(opcode) = Opcode::ALL
+            .iter()
+            .find(|o| o.mnemonic().eq_ignore_ascii_case(&name))
+        {
+            let mut operands = Vec::new();
+            if !cur.at_end() {
+                loop {
+                    cur.skip_whitespace();
+                    let column = cur.column();
+                    let (mode, e) = cur.operand()?;
+
+                    if mode == Mode::Immediate && opcode.writes_param(operands.len()) {
+                        return cur.error_at(
+                            column,
+                            format!(
+                                "Operand {} of {} is written to and cannot be immediate",
+                                operands.len() + 1,
+                                opcode
+                            ),
+                        );
+                    }
+
+                    operands.push((mode, e));
+                    if !cur.eat(',') {
+                        break;
+                    }
+                }
+            }
+
+            if operands.len() != opcode.n_params() {
+                return cur.error_at(
+                    column,
+                    format!(
+                        "{} takes {} operands, got {}",
+                        opcode,
+                        opcode.n_params(),
+                        operands.len()
+                    ),
+                );
+            }
+
+            self.address += 1 + operands.len();
+            self.items.push((
+                line_no,
+                Item::Op {
+                    opcode: *opcode,
+                    operands,
+                },
+            ));
+        } else {
+            return cur.error_at(column, format!("Unknown mnemonic '{}'", name));
+        }
+
+        if !cur.at_end() {
+            return cur.error("Unexpected trailing characters".to_owned());
+        }
+
+        Ok(())
+    }
+
+    fn eval(&self, line: usize, e: &Expr) -> AsmResult<i64> {
+        let mut out = 0;
+        for (sign, t) in &e.terms {
+            let v = match t {
+                Term::Number(v) => *v,
+                Term::Symbol { name, column } => {
+                    if let Some(v) = self.values.get(name) {
+                        *v
+                    } else if let Some(a) = self.labels.get(name) {
+                        *a as i64
+                    } else {
+                        return Err(AsmError {
+                            line,
+                            column: *column,
+                            message: format!("Undefined symbol '{}'", name),
+                        });
+                    }
+                }
+            };
+            out = match sign.checked_mul(v).and_then(|v| v.checked_add(out)) {
+                Some(v) => v,
+                None => {
+                    return Err(AsmError {
+                        line,
+                        column: e.column,
+                        message: "Value does not fit into 64 bits".to_owned(),
+                    })
+                }
+            };
+        }
+        Ok(out)
+    }
+
+    fn emit(mut self) -> AsmResult<Vec<i64>> {
+        // constants may refer to labels and to previously defined constants
+        for (name, e, line) in std::mem::take(&mut self.constants) {
+            let v = self.eval(line, &e)?;
+            self.values.insert(name, v);
+        }
+
+        let mut out = Vec::with_capacity(self.address);
+        for (line, item) in &self.items {
+            match item {
+                Item::Op { opcode, operands } => {
+                    let operands = operands
+                        .iter()
+                        .map(|(mode, e)| {
+                            Ok(Operand {
+                                mode: *mode,
+                                value: self.eval(*line, e)?,
+                            })
+                        })
+                        .collect::<AsmResult<Vec<Operand>>>()?;
+
+                    let instruction = Instruction::Op {
+                        opcode: *opcode,
+                        operands,
+                    };
+                    out.extend(instruction.encode());
+                }
+                Item::Data(values) => {
+                    for e in values {
+                        out.push(self.eval(*line, e)?);
+                    }
+                }
+            }
+        }
+
+        Ok(out)
+    }
+}
+
+/// Assemble a program from its textual representation.
+pub fn assemble(src: &str) -> std::result::Result<Vec<i64>, AsmError> {
+    let mut asm = Assembler::new();
+
+    for (i, line) in src.lines().enumerate() {
+        asm.parse_line(i + 1, line)?;
+    }
+
+    asm.emit()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::disasm::listing;
+    use crate::intcode::{parse_program, IntCodeResult, State};
+    use crate::util::read_to_string;
+
+    #[test]
+    fn test_countdown() {
+        let src = "
+            const START = 3
+
+                    ADD #START, #0, [counter]
+            loop:   OUT [counter]       ; print the counter
+                    ADD [counter], #-1, [counter]
+                    JT [counter], #loop
+                    HLT
+            counter: data 0
+        ";
+
+        let program = assemble(src).unwrap();
+        assert_eq!(
+            vec![1101, 3, 0, 14, 4, 14, 1001, 14, -1, 14, 1005, 14, 4, 99, 0],
+            program
+        );
+
+        let mut state = State::new(program);
+        let mut outputs = Vec::new();
+        while let IntCodeResult::Output = state.run(&mut outputs).unwrap() {}
+
+        assert_eq!(vec![3, 2, 1], outputs);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = assemble("  ADD #1, #2").unwrap_err();
+        assert_eq!((1, 3), (err.line, err.column));
+
+        let err = assemble("HLT\n  OUT [missing]").unwrap_err();
+        assert_eq!((2, 8), (err.line, err.column));
+
+        let err = assemble("IN #3").unwrap_err();
+        assert_eq!((1, 4), (err.line, err.column));
+
+        let err = assemble("    5: HLT").unwrap_err();
+        assert_eq!((1, 5), (err.line, err.column));
+
+        let err = assemble("HLT\ndata 1, 9223372036854775807+1").unwrap_err();
+        assert_eq!((2, 9), (err.line, err.column));
+    }
+
+    #[test]
+    fn test_extreme_values() {
+        let program = vec![1101, i64::MIN, i64::MAX, 7, 99, i64::MIN, -1];
+        assert_eq!(program, assemble(&listing(&program)).unwrap());
+
+        assert_eq!(
+            vec![i64::MIN + 2],
+            assemble("data -9223372036854775807-1+2").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_listing_roundtrip() {
+        for day in &["day09", "day21", "day25"] {
+            let path = format!("data/{}/input", day);
+            let program = parse_program(&read_to_string(&path).unwrap()).unwrap();
+
+            assert_eq!(program, assemble(&listing(&program)).unwrap());
+        }
+    }
+}
@@ -0,0 +1,410 @@
+//! Control-flow graphs of Intcode programs.
+//!
+//! Blocks are discovered by following control flow from address 0. Jumps with an immediate
+//! condition are treated as unconditional (or never taken), and jumps with an immediate target
+//! are resolved. Other jump targets are only known at run time and are marked as indirect.
+//!
+//! Compiled Intcode calls functions by storing the return address at `rb+0` and jumping to the
+//! function, which moves the relative base up on entry and back down before returning through
+//! the stored address:
+//!
+//! ```text
+//!     ADD #ret, #0, rb+0      ; or MUL #ret, #1, rb+0
+//!     JT #1, #function
+//! ret:
+//!     ...
+//! function:
+//!     ARB #3
+//!     ...
+//!     ARB #-3
+//!     JT #1, rb+0
+//! ```
+//!
+//! Such calls are resolved to both the called function and the return address, and the final
+//! jumps of functions are marked as returns.
+
+use super::disasm::{decode, Instruction, Line, Mode, Opcode, Operand};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control leaves a basic block.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Exit {
+    /// Execution continues with the block starting at the given address.
+    Next(usize),
+    /// An unconditional jump.
+    Jump(usize),
+    /// A conditional jump.
+    Branch {
+        taken: usize,
+        not_taken: usize,
+    },
+    /// A jump to an address computed at run time, and the address reached if it is not taken.
+    Indirect {
+        not_taken: Option<usize>,
+    },
+    /// A function call, continuing at `ret` when the function returns.
+    Call {
+        target: usize,
+        ret: usize,
+    },
+    /// A call through a function pointer.
+    IndirectCall {
+        ret: usize,
+    },
+    /// A return from a function through the address stored at the relative base.
+    Return,
+    Halt,
+    /// Execution runs into a word that is not a valid instruction.
+    Invalid(usize),
+}
+
+impl Exit {
+    /// Addresses of blocks that can follow within the same function, labelled by how they are
+    /// reached.
+    pub fn successors(&self) -> Vec<(&'static str, usize)> {
+        match *self {
+            Exit::Next(a) => vec![("", a)],
+            Exit::Jump(a) => vec![("", a)],
+            Exit::Branch { taken, not_taken } => vec![("taken", taken), ("not taken", not_taken)],
+            Exit::Indirect { not_taken } => {
+                not_taken.map(|a| ("not taken", a)).into_iter().collect()
+            }
+            Exit::Call { target, ret } => vec![("call", target), ("return", ret)],
+            Exit::IndirectCall { ret } => vec![("return", ret)],
+            Exit::Return | Exit::Halt | Exit::Invalid(_) => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Block {
+    pub start: usize,
+    pub lines: Vec<Line>,
+    pub exit: Exit,
+}
+
+impl Block {
+    /// Address one past the last instruction of the block.
+    pub fn end(&self) -> usize {
+        match self.lines.last() {
+            Some(l) => l.address + l.instruction.size(),
+            None => self.start,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+}
+
+/// Split a decoded instruction into its opcode and operands, or `None` for data.
+fn op(instruction: &Instruction) -> Option<(Opcode, &[Operand])> {
+    match instruction {
+        Instruction::Op { opcode, operands } => Some((*opcode, operands)),
+        Instruction::Data(_) => None,
+    }
+}
+
+/// The return address pushed by a call sequence ending in `prev`, if it is one.
+fn pushed_return_address(prev: &Instruction) -> Option<usize> {
+    let (opcode, operands) = op(prev)?;
+    let identity = match opcode {
+        Opcode::Add => 0,
+        Opcode::Mul => 1,
+        _ => return None,
+    };
+
+    let (a, b, dst) = (operands[0], operands[1], operands[2]);
+    if dst
+        != (Operand {
+            mode: Mode::Relative,
+            value: 0,
+        })
+    {
+        return None;
+    }
+
+    let ret = match (a.mode, b.mode) {
+        (Mode::Immediate, Mode::Immediate) if b.value == identity => a.value,
+        (Mode::Immediate, Mode::Immediate) if a.value == identity => b.value,
+        _ => return None,
+    };
+
+    if ret >= 0 {
+        Some(ret as usize)
+    } else {
+        None
+    }
+}
+
+/// Whether `prev` moves the relative base down, as in a function epilogue.
+fn is_epilogue(prev: &Instruction) -> bool {
+    match op(prev) {
+        Some((Opcode::AdjustRelativeBase, operands)) => {
+            operands[0].mode == Mode::Immediate && operands[0].value < 0
+        }
+        _ => false,
+    }
+}
+
+/// The exit of a block ending with `instruction` at `address`, or `None` if the instruction
+/// does not end a block. `prev` is the preceding instruction, if it is in the same block.
+fn exit_of(prev: Option<&Instruction>, address: usize, instruction: &Instruction) -> Option<Exit> {
+    let (opcode, operands) = op(instruction)?;
+    let next = address + instruction.size();
+
+    let cond = match opcode {
+        Opcode::Halt => return Some(Exit::Halt),
+        Opcode::JumpIfTrue => |v: i64| v != 0,
+        Opcode::JumpIfFalse => |v: i64| v == 0,
+        _ => return None,
+    };
+
+    let (condition, target) = (operands[0], operands[1]);
+    let always = match condition.mode {
+        Mode::Immediate if cond(condition.value) => true,
+        Mode::Immediate => return Some(Exit::Next(next)),
+        _ => false,
+    };
+
+    let pushed = if always {
+        prev.and_then(pushed_return_address)
+    } else {
+        None
+    };
+
+    let exit = match (target.mode, pushed) {
+        (Mode::Immediate, Some(ret)) if target.value >= 0 => Exit::Call {
+            target: target.value as usize,
+            ret,
+        },
+        (Mode::Immediate, None) if target.value >= 0 && always => Exit::Jump(target.value as usize),
+        (Mode::Immediate, None) if target.value >= 0 => Exit::Branch {
+            taken: target.value as usize,
+            not_taken: next,
+        },
+        (Mode::Relative, None) if always && prev.map(is_epilogue).unwrap_or(false) => Exit::Return,
+        (_, Some(ret)) => Exit::IndirectCall { ret },
+        _ => Exit::Indirect {
+            not_taken: if always { None } else { Some(next) },
+        },
+    };
+
+    Some(exit)
+}
+
+impl Cfg {
+    pub fn build(program: &[i64]) -> Self {
+        // find the reachable instructions and the addresses that start blocks
+        let mut instructions = BTreeMap::new();
+        let mut leaders = BTreeSet::new();
+        let mut todo = vec![0];
+
+        while let Some(start) = todo.pop() {
+            leaders.insert(start);
+
+            let mut address = start;
+            let mut prev = None;
+            while address < program.len() {
+                if instructions.contains_key(&address) {
+                    // joined a run of instructions that was already walked
+                    leaders.insert(address);
+                    break;
+                }
+
+                let instruction = decode(program, address);
+                if op(&instruction).is_none() {
+                    break;
+                }
+
+                let exit = exit_of(prev.as_ref(), address, &instruction);
+                let size = instruction.size();
+                instructions.insert(address, instruction.clone());
+
+                if let Some(exit) = exit {
+                    for (_, a) in exit.successors() {
+                        todo.push(a);
+                    }
+                    break;
+                }
+
+                prev = Some(instruction);
+                address += size;
+            }
+        }
+
+        // cut the instructions into blocks
+        let mut blocks = BTreeMap::new();
+        for &start in &leaders {
+            let mut lines: Vec<Line> = Vec::new();
+            let mut address = start;
+
+            let exit = loop {
+                let instruction = match instructions.get(&address) {
+                    Some(i) => i.clone(),
+                    None => break Exit::Invalid(address),
+                };
+
+                let prev = lines.last().map(|l| &l.instruction);
+                let exit = exit_of(prev, address, &instruction);
+                address += instruction.size();
+                lines.push(Line {
+                    address: address - instruction.size(),
+                    instruction,
+                });
+
+                match exit {
+                    Some(e) => break e,
+                    None if leaders.contains(&address) => break Exit::Next(address),
+                    None => {}
+                }
+            };
+
+            blocks.insert(start, Block { start, lines, exit });
+        }
+
+        Cfg { blocks }
+    }
+
+    /// Addresses of the entry point and all called functions.
+    pub fn functions(&self) -> BTreeSet<usize> {
+        let mut out: BTreeSet<usize> = self.blocks.keys().take(1).copied().collect();
+        for block in self.blocks.values() {
+            if let Exit::Call { target, .. } = block.exit {
+                out.insert(target);
+            }
+        }
+        out
+    }
+
+    /// Render the graph in Graphviz DOT format.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        let w = &mut out;
+
+        let _ = writeln!(w, "digraph cfg {{");
+        let _ = writeln!(w, "    node [shape=box, fontname=\"monospace\"];");
+
+        let functions = self.functions();
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            for line in &block.lines {
+                label += &format!("{}\\l", line);
+            }
+            if let Exit::Invalid(a) = block.exit {
+                label += &format!("{:5}: (invalid)\\l", a);
+            }
+
+            let mut attrs = format!("label=\"{}\"", label);
+            if functions.contains(&block.start) {
+                attrs += ", style=bold";
+            }
+            match block.exit {
+                Exit::Indirect { .. } | Exit::IndirectCall { .. } => attrs += ", color=red",
+                Exit::Return => attrs += ", color=blue",
+                Exit::Halt => attrs += ", peripheries=2",
+                _ => {}
+            }
+
+            let _ = writeln!(w, "    b{} [{}];", block.start, attrs);
+        }
+
+        for block in self.blocks.values() {
+            for (label, target) in block.exit.successors() {
+                let style = match label {
+                    "call" => ", style=dashed",
+                    "return" => ", style=dotted",
+                    _ => "",
+                };
+
+                let _ = writeln!(
+                    w,
+                    "    b{} -> b{} [label=\"{}\"{}];",
+                    block.start, target, label, style
+                );
+            }
+        }
+
+        let _ = writeln!(w, "}}");
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::parse_program;
+    use crate::util::read_to_string;
+
+    #[test]
+    fn test_call_and_return() {
+        let program = assemble(
+            "
+                    ARB #100
+                    IN rb+1
+                    ADD #ret, #0, rb+0
+                    JT #1, #double
+            ret:    OUT rb+1
+                    JF rb+1, #0
+                    HLT
+
+            double: ARB #2
+                    MUL rb-1, #2, rb-1
+                    ARB #-2
+                    JT #1, rb+0
+            ",
+        )
+        .unwrap();
+
+        let cfg = Cfg::build(&program);
+        let exits: Vec<(usize, Exit)> = cfg.blocks.values().map(|b| (b.start, b.exit)).collect();
+
+        assert_eq!(
+            vec![
+                (
+                    0,
+                    Exit::Call {
+                        target: 17,
+                        ret: 11
+                    }
+                ),
+                (
+                    11,
+                    Exit::Branch {
+                        taken: 0,
+                        not_taken: 16
+                    }
+                ),
+                (16, Exit::Halt),
+                (17, Exit::Return),
+            ],
+            exits
+        );
+
+        let functions: Vec<usize> = cfg.functions().into_iter().collect();
+        assert_eq!(vec![0, 17], functions);
+        assert!(cfg
+            .to_dot()
+            .contains("b0 -> b17 [label=\"call\", style=dashed];"));
+    }
+
+    #[test]
+    fn test_day21() {
+        let program = parse_program(&read_to_string("data/day21/input").unwrap()).unwrap();
+        let cfg = Cfg::build(&program);
+
+        let count = |f: fn(&Exit) -> bool| cfg.blocks.values().filter(|b| f(&b.exit)).count();
+        assert_eq!(166, cfg.blocks.len());
+        assert_eq!(48, count(|e| matches!(e, Exit::Call { .. })));
+        assert_eq!(12, count(|e| *e == Exit::Return));
+        assert_eq!(0, count(|e| matches!(e, Exit::Indirect { .. })));
+
+        // a call through a function pointer
+        assert_eq!(1, count(|e| matches!(e, Exit::IndirectCall { ret: 2037 })));
+    }
+}
@@ -0,0 +1,265 @@
+//! Code coverage of Intcode programs.
+//!
+//! Coverage records which addresses were executed and which directions conditional jumps took.
+//! It is kept across `State::restart`, and coverage collected by different machines can be
+//! merged, so that a program can be run with many different inputs before looking at the code
+//! paths none of them exercised.
+
+use super::disasm::{decode, Instruction, Line, Mode, Opcode};
+use super::memory::Memory;
+use super::State;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How often a conditional jump was taken and not taken.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Branch {
+    /// Whether the jump went both ways.
+    pub fn is_covered(&self) -> bool {
+        self.taken > 0 && self.not_taken > 0
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Coverage {
+    /// Addresses of executed instructions.
+    pub executed: BTreeSet<usize>,
+    /// Conditional jumps by address.
+    pub branches: BTreeMap<usize, Branch>,
+}
+
+/// Whether `instruction` is a jump whose condition is only known at run time.
+fn is_conditional(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Op {
+            opcode: Opcode::JumpIfTrue | Opcode::JumpIfFalse,
+            operands,
+        } => operands[0].mode != Mode::Immediate,
+        _ => false,
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage::default()
+    }
+
+    pub(super) fn record_execution(&mut self, address: usize) {
+        self.executed.insert(address);
+    }
+
+    pub(super) fn record_branch(&mut self, address: usize, taken: bool) {
+        let branch = self.branches.entry(address).or_default();
+        if taken {
+            branch.taken += 1;
+        } else {
+            branch.not_taken += 1;
+        }
+    }
+
+    /// Add the coverage collected in `other`.
+    pub fn merge(&mut self, other: &Coverage) {
+        self.executed.extend(other.executed.iter().copied());
+        for (&address, branch) in &other.branches {
+            let b = self.branches.entry(address).or_default();
+            b.taken += branch.taken;
+            b.not_taken += branch.not_taken;
+        }
+    }
+
+    /// Render a listing of `program`, marking each line as executed (`+`), executed with a
+    /// conditional jump that only went one way (`~`) or not executed (`-`). Runs of unexecuted
+    /// words are preceded by a note giving their extent. Jumps with an immediate condition are
+    /// not counted as conditional.
+    pub fn listing(&self, program: &[i64]) -> String {
+        let mut out = String::new();
+        let w = &mut out;
+
+        let conditional: Vec<&Branch> = self
+            .branches
+            .iter()
+            .filter(|(&a, _)| is_conditional(&decode(program, a)))
+            .map(|(_, b)| b)
+            .collect();
+        let covered = conditional.iter().filter(|b| b.is_covered()).count();
+        let _ = writeln!(
+            w,
+            "; {} instructions executed, {} of {} conditional jumps went both ways",
+            self.executed.len(),
+            covered,
+            conditional.len()
+        );
+
+        let mut address = 0;
+        let mut in_gap = false;
+        while address < program.len() {
+            let executed = self.executed.contains(&address);
+            let mut instruction = decode(program, address);
+
+            let marker = if executed {
+                in_gap = false;
+                match self.branches.get(&address) {
+                    Some(b) if !b.is_covered() && is_conditional(&instruction) => '~',
+                    _ => '+',
+                }
+            } else {
+                // an unexecuted instruction must not swallow the start of an executed one
+                let end = address + instruction.size();
+                if self.executed.range(address + 1..end).next().is_some() {
+                    instruction = Instruction::Data(program[address]);
+                }
+
+                if !in_gap {
+                    let next = self.executed.range(address..).next();
+                    let gap_end = next.copied().unwrap_or(program.len());
+                    let _ = writeln!(w, "; not executed: {}..{}", address, gap_end);
+                    in_gap = true;
+                }
+                '-'
+            };
+
+            let size = instruction.size();
+            let line = Line {
+                address,
+                instruction,
+            };
+            let _ = write!(w, "{} {}", marker, line);
+            if let (true, Some(b)) = (executed, self.branches.get(&address)) {
+                let _ = write!(w, "    ; taken {}, not taken {}", b.taken, b.not_taken);
+            }
+            let _ = writeln!(w);
+
+            address += size;
+        }
+
+        out
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Start collecting code coverage in `State::coverage`.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Stop collecting coverage, returning what was collected.
+    pub fn disable_coverage(&mut self) -> Option<Coverage> {
+        self.coverage.take()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::{parse_program, IntCodeResult};
+    use crate::util::read_to_string;
+
+    fn run(state: &mut State, inputs: &[i64]) -> Vec<i64> {
+        state.inputs.extend(inputs);
+        let mut outputs = Vec::new();
+        while state.run(&mut outputs).unwrap() != IntCodeResult::Halt {}
+        outputs
+    }
+
+    #[test]
+    fn test_coverage() {
+        // output 1 for a negative input and 0 otherwise
+        let program = assemble(
+            "
+                    IN [v]
+                    LT [v], #0, [v]
+                    JT [v], #neg
+                    OUT #0
+                    HLT
+            neg:    OUT #1
+                    HLT
+            v:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program.clone());
+        state.enable_coverage();
+        assert_eq!(vec![0], run(&mut state, &[5]));
+
+        let coverage = state.coverage.as_ref().unwrap();
+        assert_eq!(
+            vec![0, 2, 6, 9, 11],
+            coverage.executed.iter().copied().collect::<Vec<_>>()
+        );
+        assert_eq!(
+            Some(&Branch {
+                taken: 0,
+                not_taken: 1
+            }),
+            coverage.branches.get(&6)
+        );
+        assert_eq!(
+            "\
+; 5 instructions executed, 0 of 1 conditional jumps went both ways
++     0: IN [15]
++     2: LT [15], #0, [15]
+~     6: JT [15], #12    ; taken 0, not taken 1
++     9: OUT #0
++    11: HLT
+; not executed: 12..16
+-    12: OUT #1
+-    14: HLT
+-    15: DATA 0
+",
+            coverage.listing(&program)
+        );
+
+        // coverage is kept across restarts
+        state.restart(&program);
+        assert_eq!(vec![1], run(&mut state, &[-5]));
+        let coverage = state.disable_coverage().unwrap();
+        assert_eq!(7, coverage.executed.len());
+        assert!(coverage.branches[&6].is_covered());
+        assert!(coverage
+            .listing(&program)
+            .contains("\n; not executed: 15..16\n-    15: DATA 0\n"));
+    }
+
+    #[test]
+    fn test_merge_day19() {
+        let program = parse_program(&read_to_string("data/day19/input").unwrap()).unwrap();
+
+        let mut merged = Coverage::new();
+        let mut outputs = Vec::new();
+        for (x, y) in &[(0, 0), (3, 4), (20, 20), (40, 49)] {
+            let mut state = State::new(program.clone());
+            state.enable_coverage();
+            outputs.extend(run(&mut state, &[*x, *y]));
+
+            let coverage = state.coverage.unwrap();
+            merged.merge(&coverage);
+            assert!(merged.executed.is_superset(&coverage.executed));
+        }
+
+        assert_eq!(vec![1, 1, 0, 0], outputs);
+        assert_eq!(117, merged.executed.len());
+        assert_eq!(24, merged.branches.len());
+        let total: u64 = merged
+            .branches
+            .values()
+            .map(|b| b.taken + b.not_taken)
+            .sum();
+        assert_eq!(304, total);
+
+        let covered = merged
+            .branches
+            .iter()
+            .filter(|(_, b)| b.is_covered())
+            .map(|(&a, b)| (a, b.taken, b.not_taken))
+            .collect::<Vec<_>>();
+        assert_eq!(vec![(309, 36, 10), (350, 20, 16)], covered);
+    }
+}
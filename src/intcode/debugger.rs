@@ -0,0 +1,206 @@
+use super::disasm::{decode_with, Instruction};
+use super::history::Undone;
+use super::memory::Memory;
+use super::watch::MemoryAccess;
+use super::{IntCodeResult, State};
+use crate::result::Result;
+use std::collections::BTreeSet;
+
+/// Why the debugger stopped executing.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum StopReason {
+    /// A single step was completed.
+    Stepped,
+    /// The instruction counter reached a breakpoint.
+    Breakpoint(usize),
+    /// The user-supplied condition became true.
+    Condition,
+    /// The program wants input, but the input queue is empty.
+    Input,
+    /// The program produced an output.
+    Output,
+    Halt,
+    /// A watchpoint was triggered.
+    Watch(MemoryAccess),
+    /// The program is stuck in an infinite loop.
+    InfiniteLoop,
+}
+
+/// Wraps an Intcode machine for interactive inspection and stepwise execution.
+#[derive(Debug)]
+pub struct Debugger<M: Memory = Vec<i64>> {
+    pub state: State<M>,
+    pub outputs: Vec<i64>,
+    pub breakpoints: BTreeSet<usize>,
+    pub stop_on_output: bool,
+}
+
+impl<M: Memory<Word = i64>> Debugger<M> {
+    pub fn new(state: State<M>) -> Self {
+        Debugger {
+            state,
+            outputs: Vec::new(),
+            breakpoints: BTreeSet::new(),
+            stop_on_output: false,
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    pub fn remove_breakpoint(&mut self, address: usize) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    /// Decode the instruction at the instruction counter.
+    pub fn current_instruction(&self) -> Instruction {
+        decode_with(|a| self.state.memory.fetch(a), self.state.ic)
+    }
+
+    /// Read a memory cell. Cells past the end of memory read as zero.
+    pub fn peek(&self, address: usize) -> i64 {
+        self.state.memory.read(address)
+    }
+
+    /// Write a memory cell, growing memory if needed. The write can be undone with `step_back`.
+    pub fn poke(&mut self, address: usize, value: i64) {
+        self.state.poke(address, value);
+    }
+
+    pub fn push_input(&mut self, value: i64) {
+        self.state.inputs.push_back(value);
+    }
+
+    /// Queue a line of text as ASCII input, terminated by a newline.
+    pub fn push_ascii_line(&mut self, line: &str) {
+        self.state.inputs.extend(line.chars().map(|c| c as i64));
+        self.state.inputs.push_back(10);
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Result<StopReason> {
+        let res = match self.state.step(&mut self.outputs)? {
+            None => StopReason::Stepped,
+            Some(IntCodeResult::Input) => StopReason::Input,
+            Some(IntCodeResult::Output) => StopReason::Output,
+            Some(IntCodeResult::Halt) => StopReason::Halt,
+            Some(IntCodeResult::Watch(access)) => StopReason::Watch(access),
+            Some(IntCodeResult::InfiniteLoop) => StopReason::InfiniteLoop,
+            // only returned by `State::run`
+            Some(IntCodeResult::BudgetExhausted) => StopReason::Stepped,
+        };
+
+        Ok(res)
+    }
+
+    /// Undo the last executed instruction, if history is enabled on the machine.
+    ///
+    /// An output produced by the undone instruction is also removed from the output buffer if it
+    /// has not been taken yet.
+    pub fn step_back(&mut self) -> Option<Undone> {
+        let undone = self.state.step_back()?;
+        if undone.output.is_some() {
+            self.outputs.pop();
+        }
+        Some(undone)
+    }
+
+    /// Run until `cond` holds after an instruction, a breakpoint or watchpoint is hit, the program
+    /// waits for input or halts. Outputs only stop execution if `stop_on_output` is set.
+    ///
+    /// A breakpoint at the instruction counter when starting does not stop execution, so that
+    /// execution can be resumed from a breakpoint.
+    pub fn run_until<F: FnMut(&State<M>) -> bool>(&mut self, mut cond: F) -> Result<StopReason> {
+        let mut first = true;
+
+        loop {
+            if !first && self.breakpoints.contains(&self.state.ic) {
+                return Ok(StopReason::Breakpoint(self.state.ic));
+            }
+            first = false;
+
+            match self.step()? {
+                StopReason::Output if self.stop_on_output => return Ok(StopReason::Output),
+                StopReason::Stepped | StopReason::Output => {}
+                reason => return Ok(reason),
+            }
+
+            if cond(&self.state) {
+                return Ok(StopReason::Condition);
+            }
+        }
+    }
+
+    /// Run until a breakpoint is hit, the program waits for input or halts.
+    pub fn cont(&mut self) -> Result<StopReason> {
+        self.run_until(|_| false)
+    }
+
+    /// Take all outputs produced since the last call.
+    pub fn take_outputs(&mut self) -> Vec<i64> {
+        std::mem::take(&mut self.outputs)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+
+    fn countdown() -> Debugger {
+        let program = assemble(
+            "
+                    IN [counter]
+            loop:   OUT [counter]
+                    ADD [counter], #-1, [counter]
+                    JT [counter], #loop
+                    HLT
+            counter: data 0
+            ",
+        )
+        .unwrap();
+
+        Debugger::new(State::new(program))
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut dbg = countdown();
+
+        assert_eq!(StopReason::Input, dbg.cont().unwrap());
+        dbg.push_input(3);
+
+        dbg.add_breakpoint(2);
+        assert_eq!(StopReason::Breakpoint(2), dbg.cont().unwrap());
+        assert!(dbg.take_outputs().is_empty());
+        assert_eq!(StopReason::Breakpoint(2), dbg.cont().unwrap());
+        assert_eq!(vec![3], dbg.take_outputs());
+
+        dbg.remove_breakpoint(2);
+        assert_eq!(StopReason::Halt, dbg.cont().unwrap());
+        assert_eq!(vec![2, 1], dbg.take_outputs());
+    }
+
+    #[test]
+    fn test_step_and_condition() {
+        let mut dbg = countdown();
+        dbg.push_input(5);
+
+        assert_eq!("IN [12]", format!("{}", dbg.current_instruction()));
+        assert_eq!(StopReason::Stepped, dbg.step().unwrap());
+        assert_eq!(5, dbg.peek(12));
+
+        dbg.state.enable_history(None);
+        dbg.poke(12, 2);
+        assert_eq!(2, dbg.peek(12));
+        assert_eq!(2, dbg.step_back().unwrap().ic);
+        assert_eq!(5, dbg.peek(12));
+
+        dbg.poke(12, 2);
+        let reason = dbg.run_until(|s| s.memory[12] == 1).unwrap();
+        assert_eq!(StopReason::Condition, reason);
+        assert_eq!(vec![2], dbg.take_outputs());
+    }
+}
@@ -0,0 +1,521 @@
+//! Lifting Intcode programs into C-like pseudocode.
+//!
+//! Functions are found through the call convention described in `cfg`. A function that starts
+//! with `ARB #n` has a frame of `n` slots below its relative base: `ret` is the return address,
+//! followed by the slots `s1` to `s<n-1>`, which hold the arguments and then the locals. Slots
+//! above the relative base are the arguments of calls made by the function, and are shown as
+//! `out1`, `out2`, ... Values are returned in these slots too, so results of a call are read
+//! from `out` slots after it. Positional memory is shown as `mem[address]`.
+//!
+//! Loops and if/else statements are recognized from the layout of the blocks, as produced by a
+//! compiler. Control flow that does not fit these patterns is expressed with labels and `goto`.
+
+use super::cfg::{Block, Cfg, Exit};
+use super::disasm::{Instruction, Mode, Opcode, Operand};
+use std::collections::BTreeSet;
+
+/// Blocks of the function starting at `entry`, ordered by address. Called functions are not
+/// followed.
+pub fn function_blocks(cfg: &Cfg, entry: usize) -> Vec<&Block> {
+    let mut seen = BTreeSet::new();
+    let mut todo = vec![entry];
+
+    while let Some(a) = todo.pop() {
+        if !cfg.blocks.contains_key(&a) || !seen.insert(a) {
+            continue;
+        }
+
+        for (label, s) in cfg.blocks[&a].exit.successors() {
+            if label != "call" {
+                todo.push(s);
+            }
+        }
+    }
+
+    seen.iter().map(|a| &cfg.blocks[a]).collect()
+}
+
+/// Decompile all functions of a program.
+pub fn decompile(program: &[i64]) -> String {
+    let cfg = Cfg::build(program);
+
+    let mut out = String::new();
+    for entry in cfg.functions() {
+        if !out.is_empty() {
+            out += "\n";
+        }
+        out += &FunctionWriter::new(&cfg, entry).write();
+    }
+    out
+}
+
+fn function_name(entry: usize) -> String {
+    if entry == 0 {
+        "main".to_string()
+    } else {
+        format!("f_{}", entry)
+    }
+}
+
+fn op(instruction: &Instruction) -> Option<(Opcode, &[Operand])> {
+    match instruction {
+        Instruction::Op { opcode, operands } => Some((*opcode, operands)),
+        Instruction::Data(_) => None,
+    }
+}
+
+/// The size of the frame set up by the first instruction of a function.
+fn frame_size(entry: &Block) -> i64 {
+    match entry.lines.first().and_then(|l| op(&l.instruction)) {
+        Some((Opcode::AdjustRelativeBase, ops)) if ops[0].mode == Mode::Immediate => ops[0].value,
+        _ => 0,
+    }
+}
+
+/// A lifted statement. Assignments to `out` slots remember the slot, so that they can be
+/// turned into call arguments.
+struct Statement {
+    text: String,
+    out_slot: Option<(i64, String)>,
+}
+
+struct FunctionWriter<'a> {
+    entry: usize,
+    blocks: Vec<&'a Block>,
+    frame: i64,
+
+    /// Addresses that need a label, and addresses jumped to with `goto`.
+    labels: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+    /// Header and exit address of the loops being written, innermost last.
+    loops: Vec<(usize, usize)>,
+    out: String,
+}
+
+impl<'a> FunctionWriter<'a> {
+    fn new(cfg: &'a Cfg, entry: usize) -> Self {
+        let blocks = function_blocks(cfg, entry);
+        let frame = if entry == 0 { 0 } else { frame_size(blocks[0]) };
+
+        FunctionWriter {
+            entry,
+            blocks,
+            frame,
+            labels: BTreeSet::new(),
+            gotos: BTreeSet::new(),
+            loops: Vec::new(),
+            out: String::new(),
+        }
+    }
+
+    fn write(mut self) -> String {
+        // the first pass finds out which blocks are jumped to with goto
+        for _ in 0..2 {
+            self.labels = std::mem::take(&mut self.gotos);
+            self.out = format!("fn {}() {{", function_name(self.entry));
+            if self.frame > 0 {
+                self.out += &format!(" // frame of {} slots", self.frame);
+            }
+            self.out += "\n";
+
+            self.region(0, usize::MAX, usize::MAX, 1);
+            self.out += "}\n";
+        }
+
+        self.out
+    }
+
+    fn line(&mut self, depth: usize, text: &str) {
+        for _ in 0..depth {
+            self.out += "    ";
+        }
+        self.out += text;
+        self.out += "\n";
+    }
+
+    fn index(&self, address: usize) -> Option<usize> {
+        self.blocks.binary_search_by_key(&address, |b| b.start).ok()
+    }
+
+    /// Name of the slot at `offset` from the relative base at function entry, after the
+    /// frame was set up.
+    fn slot(&self, offset: i64) -> String {
+        let n = self.frame;
+        if offset >= 1 {
+            format!("out{}", offset)
+        } else if offset == 0 {
+            "link".to_string()
+        } else if n > 0 && offset == -n {
+            "ret".to_string()
+        } else if offset > -n {
+            format!("s{}", offset + n)
+        } else {
+            format!("rb[{}]", offset)
+        }
+    }
+
+    fn operand(&self, operand: Operand, rb: i64) -> String {
+        match operand.mode {
+            Mode::Immediate => operand.value.to_string(),
+            Mode::Position => format!("mem[{}]", operand.value),
+            Mode::Relative => self.slot(operand.value + rb),
+        }
+    }
+
+    /// Lift the instructions of a block, returning the statements and the offset of the
+    /// relative base at the end of the block.
+    fn lift(&self, block: &Block) -> (Vec<Statement>, i64) {
+        let mut rb = 0;
+        let mut out = Vec::new();
+
+        for (i, line) in block.lines.iter().enumerate() {
+            let (opcode, ops) = match op(&line.instruction) {
+                Some(o) => o,
+                None => continue,
+            };
+
+            let assign = |dst: Operand, rb: i64, value: String| {
+                let out_slot = match dst.mode {
+                    Mode::Relative if dst.value + rb >= 0 => Some((dst.value + rb, value.clone())),
+                    _ => None,
+                };
+                Statement {
+                    text: format!("{} = {};", self.operand(dst, rb), value),
+                    out_slot,
+                }
+            };
+
+            let value = match opcode {
+                Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => {
+                    let (a, b) = (self.operand(ops[0], rb), self.operand(ops[1], rb));
+                    let identity = if opcode == Opcode::Add { "0" } else { "1" };
+
+                    match opcode {
+                        Opcode::Add | Opcode::Mul if a == identity => b,
+                        Opcode::Add | Opcode::Mul if b == identity => a,
+                        Opcode::Add => format!("{} + {}", a, b),
+                        Opcode::Mul => format!("{} * {}", a, b),
+                        Opcode::LessThan => format!("{} < {}", a, b),
+                        _ => format!("{} == {}", a, b),
+                    }
+                }
+                Opcode::In => "input()".to_string(),
+                Opcode::Out => {
+                    out.push(Statement {
+                        text: format!("output({});", self.operand(ops[0], rb)),
+                        out_slot: None,
+                    });
+                    continue;
+                }
+                Opcode::AdjustRelativeBase => {
+                    let prologue = block.start == self.entry && i == 0;
+                    let epilogue = block.exit == Exit::Return && i + 2 == block.lines.len();
+
+                    match ops[0].mode {
+                        Mode::Immediate => {
+                            if prologue && self.frame == 0 {
+                                // the entry point sets up the stack
+                                out.push(Statement {
+                                    text: format!("rb = {};", ops[0].value),
+                                    out_slot: None,
+                                });
+                            } else if !prologue && !epilogue {
+                                out.push(Statement {
+                                    text: format!("rb += {};", ops[0].value),
+                                    out_slot: None,
+                                });
+                            }
+                            if !prologue {
+                                rb += ops[0].value;
+                            }
+                        }
+                        _ => out.push(Statement {
+                            text: format!("rb += {};", self.operand(ops[0], rb)),
+                            out_slot: None,
+                        }),
+                    }
+                    continue;
+                }
+                Opcode::JumpIfTrue | Opcode::JumpIfFalse | Opcode::Halt => continue,
+            };
+
+            let dst = *ops.last().unwrap_or(&ops[0]);
+            out.push(assign(dst, rb, value));
+        }
+
+        (out, rb)
+    }
+
+    /// The call made at the end of `stmts`, which end with pushing the return address. The
+    /// argument assignments preceding it are removed.
+    fn call(&self, stmts: &mut Vec<Statement>, callee: String) -> String {
+        stmts.pop();
+
+        let mut args = Vec::new();
+        while let Some((slot, value)) = stmts.last().and_then(|s| s.out_slot.clone()) {
+            if slot == 0 || args.iter().any(|(s, _)| *s == slot) {
+                break;
+            }
+            args.push((slot, value));
+            stmts.pop();
+        }
+
+        args.sort();
+        let n_args = args.last().map(|a| a.0).unwrap_or(0);
+        let args: Vec<String> = (1..=n_args)
+            .map(|slot| match args.iter().find(|a| a.0 == slot) {
+                Some((_, v)) => v.clone(),
+                None => format!("out{}", slot),
+            })
+            .collect();
+
+        format!("{}({});", callee, args.join(", "))
+    }
+
+    /// The condition under which the jump ending `block` is taken, and its negation.
+    fn condition(&self, block: &Block, rb: i64) -> (String, String, String) {
+        let (opcode, ops) = match block.lines.last().and_then(|l| op(&l.instruction)) {
+            Some((o @ Opcode::JumpIfTrue, ops)) | Some((o @ Opcode::JumpIfFalse, ops)) => (o, ops),
+            _ => return Default::default(),
+        };
+
+        let value = self.operand(ops[0], rb);
+        let target = self.operand(ops[1], rb);
+        let negated = format!("!{}", value);
+
+        match opcode {
+            Opcode::JumpIfTrue => (value, negated, target),
+            _ => (negated, value, target),
+        }
+    }
+
+    fn goto(&mut self, address: usize) -> String {
+        match self.loops.last() {
+            Some(&(header, _)) if header == address => "continue;".to_string(),
+            Some(&(_, exit)) if exit == address => "break;".to_string(),
+            _ => {
+                self.gotos.insert(address);
+                format!("goto L_{};", address)
+            }
+        }
+    }
+
+    /// Continue at `address` after the block at index `i` of a region ending at `to`, where
+    /// execution continues at `follow` after the region.
+    fn jump(&mut self, depth: usize, i: usize, to: usize, follow: usize, address: usize) {
+        let next = self.blocks.get(i + 1).map(|b| b.start).filter(|&a| a < to);
+        if next == Some(address) || (next.is_none() && address == follow) {
+            return;
+        }
+
+        let text = self.goto(address);
+        self.line(depth, &text);
+    }
+
+    /// The exit of the loop with its header at index `i`, if it fits into a region ending at
+    /// `to`. The loop extends up to the last block jumping back to the header.
+    fn loop_exit(&self, i: usize, to: usize, follow: usize) -> Option<usize> {
+        let header = self.blocks[i].start;
+        let latch = (i..self.blocks.len()).rev().find(|&j| {
+            self.blocks[j]
+                .exit
+                .successors()
+                .iter()
+                .any(|&(label, a)| label != "call" && a == header)
+        })?;
+
+        let exit = match self.blocks.get(latch + 1) {
+            Some(b) => b.start,
+            None => self.blocks[latch].end(),
+        };
+
+        if exit < to || exit == follow {
+            Some(exit)
+        } else {
+            None
+        }
+    }
+
+    /// Write the blocks from index `i` up to address `to`.
+    fn region(&mut self, mut i: usize, to: usize, follow: usize, depth: usize) {
+        while i < self.blocks.len() && self.blocks[i].start < to {
+            let block = self.blocks[i];
+
+            if self.loops.last().map(|l| l.0) != Some(block.start) {
+                if let Some(exit) = self.loop_exit(i, to, follow) {
+                    if self.labels.contains(&block.start) {
+                        self.line(depth - 1, &format!("L_{}:", block.start));
+                    }
+                    self.line(depth, "loop {");
+                    self.loops.push((block.start, exit));
+                    self.region(i, exit, block.start, depth + 1);
+                    self.loops.pop();
+                    self.line(depth, "}");
+
+                    i = self.index(exit).unwrap_or(self.blocks.len());
+                    continue;
+                }
+            }
+
+            if self.labels.contains(&block.start)
+                && self.loops.last().map(|l| l.0) != Some(block.start)
+            {
+                self.line(depth - 1, &format!("L_{}:", block.start));
+            }
+
+            let (mut stmts, rb) = self.lift(block);
+            let (cond, negated, target) = self.condition(block, rb);
+
+            let call = match block.exit {
+                Exit::Call { target, .. } => Some(self.call(&mut stmts, function_name(target))),
+                Exit::IndirectCall { .. } => Some(self.call(&mut stmts, format!("(*{})", target))),
+                _ => None,
+            };
+
+            for s in &stmts {
+                self.line(depth, &s.text);
+            }
+            if let Some(call) = call {
+                self.line(depth, &call);
+            }
+
+            match block.exit {
+                Exit::Next(a) | Exit::Jump(a) => self.jump(depth, i, to, follow, a),
+                Exit::Call { ret, .. } | Exit::IndirectCall { ret } => {
+                    self.jump(depth, i, to, follow, ret)
+                }
+                Exit::Branch { taken, not_taken } => {
+                    let next = self.blocks.get(i + 1).map(|b| b.start);
+                    // structured statements have to end where the region continues
+                    let ends = |a: usize| a < to || a == follow;
+                    let then_end = self.index(taken).filter(|&t| t > i + 1 && ends(taken));
+
+                    match then_end {
+                        Some(t) if next == Some(not_taken) => {
+                            let else_end = match self.blocks[t - 1].exit {
+                                Exit::Jump(j) if j > taken && ends(j) => self.index(j).map(|_| j),
+                                _ => None,
+                            };
+
+                            self.line(depth, &format!("if ({}) {{", negated));
+                            match else_end {
+                                Some(j) => {
+                                    self.region(i + 1, taken, j, depth + 1);
+                                    self.line(depth, "} else {");
+                                    self.region(t, j, j, depth + 1);
+                                    self.line(depth, "}");
+                                    i = self.index(j).unwrap_or(self.blocks.len());
+                                }
+                                None => {
+                                    self.region(i + 1, taken, taken, depth + 1);
+                                    self.line(depth, "}");
+                                    i = t;
+                                }
+                            }
+                            continue;
+                        }
+                        _ => {
+                            let text = format!("if ({}) {}", cond, self.goto(taken));
+                            self.line(depth, &text);
+                            self.jump(depth, i, to, follow, not_taken);
+                        }
+                    }
+                }
+                Exit::Indirect { not_taken } => {
+                    match not_taken {
+                        Some(_) => self.line(depth, &format!("if ({}) goto *{};", cond, target)),
+                        None => self.line(depth, &format!("goto *{};", target)),
+                    }
+                    if let Some(a) = not_taken {
+                        self.jump(depth, i, to, follow, a);
+                    }
+                }
+                Exit::Return => self.line(depth, "return;"),
+                Exit::Halt => self.line(depth, "halt();"),
+                Exit::Invalid(a) => self.line(depth, &format!("invalid({});", a)),
+            }
+
+            i += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::parse_program;
+    use crate::util::read_to_string;
+
+    #[test]
+    fn test_decompile() {
+        // print the inputs with their sum, and whether each is positive
+        let program = assemble(
+            "
+                    ARB #1000
+            read:   IN rb+1
+                    EQ rb+1, #0, [done]
+                    JT [done], #end
+                    ADD #after, #0, rb+0
+                    JT #1, #show
+            after:  JT #1, #read
+            end:    HLT
+
+            show:   ARB #3
+                    OUT rb-2
+                    LT #0, rb-2, rb-1
+                    JF rb-1, #neg
+                    OUT #1
+                    JT #1, #out
+            neg:    OUT #0
+            out:    ADD rb-2, [sum], [sum]
+                    OUT [sum]
+                    ARB #-3
+                    JT #1, rb+0
+
+            done:   data 0
+            sum:    data 0
+            ",
+        )
+        .unwrap();
+
+        let expected = "\
+fn main() {
+    rb = 1000;
+    loop {
+        out1 = input();
+        mem[51] = out1 == 0;
+        if (mem[51]) break;
+        f_22();
+    }
+    halt();
+}
+
+fn f_22() { // frame of 3 slots
+    output(s1);
+    s2 = 0 < s1;
+    if (s2) {
+        output(1);
+    } else {
+        output(0);
+    }
+    mem[52] = s1 + mem[52];
+    output(mem[52]);
+    return;
+}
+";
+
+        assert_eq!(expected, decompile(&program));
+    }
+
+    #[test]
+    fn test_day21() {
+        let program = parse_program(&read_to_string("data/day21/input").unwrap()).unwrap();
+        let cfg = Cfg::build(&program);
+        let text = decompile(&program);
+
+        assert_eq!(cfg.functions().len(), text.matches("\nfn ").count() + 1);
+        assert!(!text.contains("invalid("));
+    }
+}
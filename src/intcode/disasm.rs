@@ -0,0 +1,404 @@
+//! Decoding Intcode instruction words and disassembling programs.
+//!
+//! Instruction words are decoded the same way the interpreter decodes them, so the listing of a
+//! program shows what the interpreter would execute at each address. Words that the interpreter
+//! would fail on are shown as data.
+
+/// Parameter mode of an instruction operand.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Mode {
+    Position,
+    Immediate,
+    Relative,
+}
+
+impl Mode {
+    pub fn from_digit(d: u8) -> Option<Self> {
+        match d {
+            0 => Some(Mode::Position),
+            1 => Some(Mode::Immediate),
+            2 => Some(Mode::Relative),
+            _ => None,
+        }
+    }
+
+    pub fn to_digit(self) -> i64 {
+        match self {
+            Mode::Position => 0,
+            Mode::Immediate => 1,
+            Mode::Relative => 2,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Opcode {
+    Add,
+    Mul,
+    In,
+    Out,
+    JumpIfTrue,
+    JumpIfFalse,
+    LessThan,
+    Equals,
+    AdjustRelativeBase,
+    Halt,
+}
+
+impl Opcode {
+    pub const ALL: [Opcode; 10] = [
+        Opcode::Add,
+        Opcode::Mul,
+        Opcode::In,
+        Opcode::Out,
+        Opcode::JumpIfTrue,
+        Opcode::JumpIfFalse,
+        Opcode::LessThan,
+        Opcode::Equals,
+        Opcode::AdjustRelativeBase,
+        Opcode::Halt,
+    ];
+
+    pub fn from_code(code: i64) -> Option<Self> {
+        match code {
+            1 => Some(Opcode::Add),
+            2 => Some(Opcode::Mul),
+            3 => Some(Opcode::In),
+            4 => Some(Opcode::Out),
+            5 => Some(Opcode::JumpIfTrue),
+            6 => Some(Opcode::JumpIfFalse),
+            7 => Some(Opcode::LessThan),
+            8 => Some(Opcode::Equals),
+            9 => Some(Opcode::AdjustRelativeBase),
+            99 => Some(Opcode::Halt),
+            _ => None,
+        }
+    }
+
+    pub fn code(self) -> i64 {
+        match self {
+            Opcode::Add => 1,
+            Opcode::Mul => 2,
+            Opcode::In => 3,
+            Opcode::Out => 4,
+            Opcode::JumpIfTrue => 5,
+            Opcode::JumpIfFalse => 6,
+            Opcode::LessThan => 7,
+            Opcode::Equals => 8,
+            Opcode::AdjustRelativeBase => 9,
+            Opcode::Halt => 99,
+        }
+    }
+
+    pub fn mnemonic(self) -> &'static str {
+        match self {
+            Opcode::Add => "ADD",
+            Opcode::Mul => "MUL",
+            Opcode::In => "IN",
+            Opcode::Out => "OUT",
+            Opcode::JumpIfTrue => "JT",
+            Opcode::JumpIfFalse => "JF",
+            Opcode::LessThan => "LT",
+            Opcode::Equals => "EQ",
+            Opcode::AdjustRelativeBase => "ARB",
+            Opcode::Halt => "HLT",
+        }
+    }
+
+    /// Number of operands following the instruction word.
+    pub fn n_params(self) -> usize {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => 3,
+            Opcode::JumpIfTrue | Opcode::JumpIfFalse => 2,
+            Opcode::In | Opcode::Out | Opcode::AdjustRelativeBase => 1,
+            Opcode::Halt => 0,
+        }
+    }
+
+    /// Whether the operand at (zero-based) index `i` is an address that gets written to.
+    pub fn writes_param(self, i: usize) -> bool {
+        match self {
+            Opcode::Add | Opcode::Mul | Opcode::LessThan | Opcode::Equals => i == 2,
+            Opcode::In => i == 0,
+            _ => false,
+        }
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{}", self.mnemonic())
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Operand {
+    pub mode: Mode,
+    pub value: i64,
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self.mode {
+            Mode::Position => write!(f, "[{}]", self.value),
+            Mode::Immediate => write!(f, "#{}", self.value),
+            Mode::Relative if self.value < 0 => write!(f, "rb{}", self.value),
+            Mode::Relative => write!(f, "rb+{}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Instruction {
+    Op {
+        opcode: Opcode,
+        operands: Vec<Operand>,
+    },
+    Data(i64),
+}
+
+impl Instruction {
+    /// Number of memory words taken up by the instruction.
+    pub fn size(&self) -> usize {
+        match self {
+            Instruction::Op { operands, .. } => 1 + operands.len(),
+            Instruction::Data(_) => 1,
+        }
+    }
+
+    /// Re-encode the instruction into memory words.
+    pub fn encode(&self) -> Vec<i64> {
+        match self {
+            Instruction::Op { opcode, operands } => {
+                let mut word = opcode.code();
+                let mut factor = 100;
+                for op in operands {
+                    word += op.mode.to_digit() * factor;
+                    factor *= 10;
+                }
+
+                let mut out = vec![word];
+                out.extend(operands.iter().map(|op| op.value));
+                out
+            }
+            Instruction::Data(v) => vec![*v],
+        }
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Instruction::Op { opcode, operands } => {
+                write!(f, "{}", opcode)?;
+                for (i, op) in operands.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", sep, op)?;
+                }
+                Ok(())
+            }
+            Instruction::Data(v) => write!(f, "DATA {}", v),
+        }
+    }
+}
+
+/// A decoded instruction together with the address it was found at.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Line {
+    pub address: usize,
+    pub instruction: Instruction,
+}
+
+impl std::fmt::Display for Line {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "{:5}: {}", self.address, self.instruction)
+    }
+}
+
+/// Decode the instruction starting at `address`.
+///
+/// Words that do not form a valid instruction (unknown opcode, unknown parameter mode, immediate
+/// mode on a write target or operands running past the end of memory) decode to
+/// `Instruction::Data`. Like the interpreter, decoding ignores mode digits beyond the parameters
+/// of the instruction, so re-encoding such a word drops them. This includes opcodes registered with `State::register_opcode`, so
+/// the control-flow graph and decompiler treat them as data as well.
+pub fn decode(memory: &[i64], address: usize) -> Instruction {
+    decode_with(|a| memory.get(a).copied(), address)
+}
+
+/// An instruction word split into its opcode and the mode digits of its (up to three)
+/// parameters, lowest digit first. Any further digits are ignored.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub struct Decoded {
+    pub opcode: i64,
+    pub modes: [u8; 3],
+}
+
+impl Decoded {
+    pub fn new(word: i64) -> Self {
+        let mut modes = [0; 3];
+        let mut v = word / 100;
+        for m in modes.iter_mut() {
+            *m = (v % 10) as u8;
+            v /= 10;
+        }
+
+        Decoded {
+            opcode: word % 100,
+            modes,
+        }
+    }
+}
+
+/// Decode the instruction starting at `address`, reading words through `fetch`, which returns
+/// `None` for addresses past the end of memory.
+pub fn decode_with<F: Fn(usize) -> Option<i64>>(fetch: F, address: usize) -> Instruction {
+    let word = match fetch(address) {
+        Some(w) => w,
+        None => return Instruction::Data(0),
+    };
+
+    let decoded = Decoded::new(word);
+
+    let opcode = match Opcode::from_code(decoded.opcode) {
+        Some(o) => o,
+        _ => return Instruction::Data(word),
+    };
+
+    let n_params = opcode.n_params();
+    let mut operands = Vec::with_capacity(n_params);
+    for i in 0..n_params {
+        let mode = match Mode::from_digit(decoded.modes[i]) {
+            Some(Mode::Immediate) if opcode.writes_param(i) => return Instruction::Data(word),
+            Some(m) => m,
+            None => return Instruction::Data(word),
+        };
+
+        let value = match fetch(address + 1 + i) {
+            Some(v) => v,
+            None => return Instruction::Data(word),
+        };
+
+        operands.push(Operand { mode, value });
+    }
+
+    Instruction::Op { opcode, operands }
+}
+
+/// Linearly sweep through a program, decoding one instruction after another.
+pub fn disassemble(program: &[i64]) -> Vec<Line> {
+    let mut out = Vec::new();
+    let mut address = 0;
+
+    while address < program.len() {
+        let instruction = decode(program, address);
+        let len = instruction.size();
+
+        out.push(Line {
+            address,
+            instruction,
+        });
+
+        address += len;
+    }
+
+    out
+}
+
+/// Render a program as a human-readable listing, one instruction per line, which assembles back
+/// into the program.
+///
+/// Instructions with ignored mode digits cannot be written in assembly, and are listed as their
+/// words followed by the instruction as a comment.
+pub fn listing(program: &[i64]) -> String {
+    let mut out = String::new();
+    for line in disassemble(program) {
+        let words = &program[line.address..line.address + line.instruction.size()];
+        if line.instruction.encode() == words {
+            out += &format!("{}\n", line);
+        } else {
+            let words: Vec<String> = words.iter().map(|w| w.to_string()).collect();
+            out += &format!(
+                "{:5}: DATA {} ; {}\n",
+                line.address,
+                words.join(", "),
+                line.instruction
+            );
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::{IntCodeResult, State};
+
+    #[test]
+    fn test_decode() {
+        let program = vec![1101, 12, 5, 3, 204, -3, 99];
+
+        assert_eq!("ADD #12, #5, [3]", format!("{}", decode(&program, 0)));
+        assert_eq!("OUT rb-3", format!("{}", decode(&program, 4)));
+        assert_eq!("HLT", format!("{}", decode(&program, 6)));
+    }
+
+    #[test]
+    fn test_decoded() {
+        let d = Decoded::new(1208);
+        assert_eq!((8, [2, 1, 0]), (d.opcode, d.modes));
+    }
+
+    #[test]
+    fn test_surplus_mode_digits() {
+        // executed like OUT #7 and HLT by the interpreter
+        let program = vec![1104, 7, 1_000_099];
+        assert_eq!("OUT #7", format!("{}", decode(&program, 0)));
+        assert_eq!("HLT", format!("{}", decode(&program, 2)));
+
+        let mut state = State::new(program.clone());
+        let mut outputs = Vec::new();
+        state.run(&mut outputs).unwrap();
+        assert_eq!(IntCodeResult::Halt, state.run(&mut outputs).unwrap());
+        assert_eq!(vec![7], outputs);
+
+        assert_eq!(
+            "    0: DATA 1104, 7 ; OUT #7\n    2: DATA 1000099 ; HLT\n",
+            listing(&program)
+        );
+    }
+
+    #[test]
+    fn test_invalid_words_are_data() {
+        // unknown opcode, immediate write target, operands past the end
+        let program = vec![42, 11101, 50, 60, 70, 1, 0];
+        let lines = disassemble(&program);
+
+        let instructions: Vec<Instruction> = lines.into_iter().map(|l| l.instruction).collect();
+        assert_eq!(
+            vec![
+                Instruction::Data(42),
+                Instruction::Data(11101),
+                Instruction::Data(50),
+                Instruction::Data(60),
+                Instruction::Data(70),
+                Instruction::Data(1),
+                Instruction::Data(0),
+            ],
+            instructions
+        );
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let program = vec![21101, 7, -2, 10, 1005, 10, 0, 209, 3, 99];
+
+        let mut encoded = Vec::new();
+        for line in disassemble(&program) {
+            encoded.extend(line.instruction.encode());
+        }
+
+        assert_eq!(program, encoded);
+    }
+}
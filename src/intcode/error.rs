@@ -0,0 +1,163 @@
+//! Errors raised by the Intcode interpreter.
+//!
+//! Every error carries the address, raw word and relative base of the failing instruction, so
+//! that it can be located in a disassembly of the program.
+
+/// The ways in which executing an Intcode instruction can fail.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub enum ErrorKind {
+    InvalidOpcode(i64),
+    /// A parameter (1-based) has a mode other than position, immediate or relative.
+    InvalidParameterMode {
+        param: usize,
+        mode: u8,
+    },
+    /// A parameter (1-based) that is written to is in immediate mode.
+    WriteInImmediateMode {
+        param: usize,
+    },
+    NegativeAddress(i64),
+    /// An instruction word or operand lies past the end of memory.
+    FetchOutOfRange(usize),
+    /// An address at or beyond the configured maximum memory size was accessed.
+    MemoryLimitExceeded(usize),
+    /// A jump to an address outside of memory.
+    JumpOutOfRange(i64),
+    /// The machine has executed its maximum number of instructions.
+    StepLimitExceeded(usize),
+    /// The result of an arithmetic instruction, or the relative base, does not fit into its type.
+    Overflow,
+    /// A word used as an opcode, address, jump target or relative base offset does not fit
+    /// into an `i64`.
+    WordOutOfRange,
+    /// The handler of a registered opcode reported an error.
+    ExtensionFailed {
+        opcode: i64,
+        message: String,
+    },
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            ErrorKind::InvalidOpcode(op) => write!(f, "Invalid opcode: {}", op),
+            ErrorKind::InvalidParameterMode { param, mode } => {
+                write!(f, "Invalid mode {} for parameter {}", mode, param)
+            }
+            ErrorKind::WriteInImmediateMode { param } => {
+                write!(f, "Parameter {} is written to in immediate mode", param)
+            }
+            ErrorKind::NegativeAddress(a) => write!(f, "Negative address: {}", a),
+            ErrorKind::FetchOutOfRange(a) => {
+                write!(f, "Instruction fetch past end of memory: {}", a)
+            }
+            ErrorKind::MemoryLimitExceeded(a) => write!(f, "Memory limit exceeded: {}", a),
+            ErrorKind::JumpOutOfRange(a) => write!(f, "Jump out of range: {}", a),
+            ErrorKind::StepLimitExceeded(n) => write!(f, "Step limit of {} exceeded", n),
+            ErrorKind::Overflow => write!(f, "Arithmetic overflow"),
+            ErrorKind::WordOutOfRange => write!(f, "Word out of range"),
+            ErrorKind::ExtensionFailed { opcode, message } => {
+                write!(f, "Opcode {} failed: {}", opcode, message)
+            }
+        }
+    }
+}
+
+/// An error raised by the Intcode interpreter, with the machine context it occurred in.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct IntCodeError {
+    pub kind: ErrorKind,
+    /// Address of the failing instruction.
+    pub ic: usize,
+    /// The raw instruction word at `ic`, or 0 if it does not fit into an `i64`.
+    pub instruction: i64,
+    pub relative_base: i64,
+}
+
+impl std::fmt::Display for IntCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(
+            f,
+            "{} (at ic={}, instruction={}, relative_base={})",
+            self.kind, self.ic, self.instruction, self.relative_base
+        )
+    }
+}
+
+impl std::error::Error for IntCodeError {}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::{IntCodeResult, State};
+
+    fn run_err(program: Vec<i64>) -> IntCodeError {
+        let mut state = State::new(program);
+        let mut outputs = Vec::new();
+        loop {
+            match state.run(&mut outputs) {
+                Ok(IntCodeResult::Halt) => panic!("Program halted without error"),
+                Ok(_) => continue,
+                Err(e) => return e,
+            }
+        }
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        let err = run_err(vec![1101, 20, 22, 4, 0]);
+        assert_eq!(ErrorKind::InvalidOpcode(42), err.kind);
+        assert_eq!((4, 42), (err.ic, err.instruction));
+
+        let err = run_err(vec![11101, 1, 1, 0]);
+        assert_eq!(ErrorKind::WriteInImmediateMode { param: 3 }, err.kind);
+
+        let err = run_err(vec![109, -5, 22201, 0, 0, 0, 99]);
+        assert_eq!(ErrorKind::NegativeAddress(-5), err.kind);
+        assert_eq!(-5, err.relative_base);
+
+        let err = run_err(vec![1105, 1, 1000]);
+        assert_eq!(ErrorKind::JumpOutOfRange(1000), err.kind);
+
+        let err = run_err(vec![1101, 1, 2, 0, 1]);
+        assert_eq!(ErrorKind::FetchOutOfRange(5), err.kind);
+
+        let err = run_err(vec![1001, 0, 1, 0]);
+        assert_eq!(ErrorKind::FetchOutOfRange(4), err.kind);
+        assert_eq!(4, err.ic);
+
+        let err = run_err(vec![1102, i64::MAX, 2, 0, 99]);
+        assert_eq!(ErrorKind::Overflow, err.kind);
+
+        let err = run_err(vec![109, i64::MAX, 109, 1, 99]);
+        assert_eq!(ErrorKind::Overflow, err.kind);
+
+        let err = run_err(vec![301, 0, 0, 0]);
+        assert_eq!(
+            ErrorKind::InvalidParameterMode { param: 1, mode: 3 },
+            err.kind
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let program = vec![1101, 1, 2, 100, 1101, 1, 2, 1_000_000_000, 99];
+
+        let mut state = State::new(program.clone());
+        state.max_memory = Some(1000);
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::MemoryLimitExceeded(1_000_000_000), err.kind);
+        assert_eq!((4, 101), (err.ic, state.memory.len()));
+    }
+
+    #[test]
+    fn test_step_limit() {
+        let mut state = State::new(vec![1105, 1, 0]);
+        state.max_steps = Some(100);
+
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::StepLimitExceeded(100), err.kind);
+        assert_eq!(100, state.steps);
+    }
+}
@@ -0,0 +1,234 @@
+//! Forking machines, and searching the states reachable from a machine by feeding it different
+//! inputs.
+//!
+//! A search starts from a root machine and a list of actions. For every action, the machine is
+//! forked and a callback applies the action to the fork, e.g. by sending a command and reading
+//! the reply, and decides how to continue from there. Forking is cheapest for machines with
+//! `PagedMemory`, whose pages are only copied when written to.
+
+use super::memory::Memory;
+use super::State;
+use crate::result::Result;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+impl<M: Memory + Clone> State<M> {
+    /// A copy of the machine that can be run independently of it.
+    ///
+    /// The copy keeps the watchpoints, limits, extensions and loop detector of the original. Like
+    /// snapshots, it does not carry over the trace, undo history, profile or coverage.
+    pub fn fork(&self) -> Self {
+        let mut state = State::with_memory(self.memory.clone());
+        state.ic = self.ic;
+        state.inputs = self.inputs.clone();
+        state.relative_base = self.relative_base;
+        state.watchpoints = self.watchpoints.clone();
+        state.steps = self.steps;
+        state.max_steps = self.max_steps;
+        state.max_memory = self.max_memory;
+        state.run_budget = self.run_budget;
+        state.loop_detector = self.loop_detector.clone();
+        state.extensions = self.extensions.clone();
+        state.watch_hits = self.watch_hits.clone();
+        state
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Strategy {
+    /// Explore states in order of the number of actions leading to them, so that the first
+    /// state found is reached by a shortest path.
+    BreadthFirst,
+    /// Follow the first action of every state as far as possible before trying the next one.
+    DepthFirst,
+}
+
+/// What to do with a state after applying an action.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Visit<A, K> {
+    /// Continue with `actions`, unless a state with the same `key` was expanded before.
+    Expand { key: K, actions: Vec<A> },
+    /// Do not continue from this state.
+    Prune,
+    /// Stop the search, and return this state.
+    Found,
+}
+
+/// A machine reached by a search, and the actions leading to it from the root.
+#[derive(Debug)]
+pub struct Node<M: Memory, A> {
+    pub state: State<M>,
+    pub path: Vec<A>,
+}
+
+/// Search the states reachable from `root`, which is identified by `key` and continues with
+/// `actions`. `apply` is called with a fork of the parent state and the path to the new state,
+/// the last element of which is the action to apply.
+///
+/// Returns the first state for which `apply` returned `Visit::Found`, or `None` once all
+/// states have been explored.
+pub fn explore<M, A, K, F>(
+    strategy: Strategy,
+    root: State<M>,
+    key: K,
+    actions: Vec<A>,
+    mut apply: F,
+) -> Result<Option<Node<M, A>>>
+where
+    M: Memory + Clone,
+    A: Clone,
+    K: Hash + Eq,
+    F: FnMut(&mut State<M>, &[A]) -> Result<Visit<A, K>>,
+{
+    let mut seen = HashSet::new();
+    seen.insert(key);
+
+    let root = Node {
+        state: root,
+        path: Vec::new(),
+    };
+    let mut frontier = VecDeque::new();
+    frontier.push_back((root, actions));
+
+    loop {
+        let next = match strategy {
+            Strategy::BreadthFirst => frontier.pop_front(),
+            Strategy::DepthFirst => frontier.pop_back(),
+        };
+        let (node, actions) = match next {
+            Some(n) => n,
+            None => return Ok(None),
+        };
+
+        let mut children = Vec::new();
+        for action in actions {
+            let mut child = Node {
+                state: node.state.fork(),
+                path: node.path.clone(),
+            };
+            child.path.push(action);
+
+            match apply(&mut child.state, &child.path)? {
+                Visit::Expand { key, actions } => {
+                    if seen.insert(key) {
+                        children.push((child, actions));
+                    }
+                }
+                Visit::Prune => {}
+                Visit::Found => return Ok(Some(child)),
+            }
+        }
+
+        match strategy {
+            Strategy::BreadthFirst => frontier.extend(children),
+            Strategy::DepthFirst => frontier.extend(children.into_iter().rev()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::board::{Direction, Position};
+    use crate::intcode::memory::PagedMemory;
+    use crate::intcode::{parse_program, IntCodeResult};
+    use crate::result::format_err;
+    use crate::util::read_to_string;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_fork() {
+        let program = parse_program(&read_to_string("data/day09/input").unwrap()).unwrap();
+        let mut state = State::with_memory(PagedMemory::from_program(program));
+        state.max_steps = Some(100_000);
+
+        let mut fork = state.fork();
+        assert_eq!(1, fork.memory.n_shared_pages());
+
+        let mut outputs = Vec::new();
+        state.inputs.push_back(1);
+        state.run(&mut outputs).unwrap();
+        assert_eq!(0, fork.memory.n_shared_pages());
+
+        // the fork still starts from the beginning, and runs into the step limit
+        fork.inputs.push_back(2);
+        assert!(fork.run(&mut outputs).is_err());
+        assert_eq!(Some(100_000), fork.max_steps);
+    }
+
+    /// Move the repair droid of day 15, returning its status code.
+    fn move_droid(state: &mut State<PagedMemory>, direction: Direction) -> Result<i64> {
+        state.inputs.push_back(direction.to_input());
+
+        let mut outputs = Vec::new();
+        match state.run(&mut outputs)? {
+            IntCodeResult::Output => Ok(outputs[0]),
+            res => Err(format_err!("Unexpected result: {:?}", res)),
+        }
+    }
+
+    fn position(path: &[Direction]) -> Position {
+        path.iter()
+            .fold(Position::ZERO, |pos, d| pos + d.to_ofs().into())
+    }
+
+    #[test]
+    fn test_day15() {
+        let program = parse_program(&read_to_string("data/day15/input").unwrap()).unwrap();
+        let root = State::with_memory(PagedMemory::from_program(program));
+
+        let to_oxygen = |strategy| {
+            explore(
+                strategy,
+                root.fork(),
+                Position::ZERO,
+                Direction::ALL.to_vec(),
+                |state, path| {
+                    Ok(match move_droid(state, *path.last().unwrap())? {
+                        0 => Visit::Prune,
+                        2 => Visit::Found,
+                        _ => Visit::Expand {
+                            key: position(path),
+                            actions: Direction::ALL.to_vec(),
+                        },
+                    })
+                },
+            )
+            .unwrap()
+            .unwrap()
+        };
+
+        let shortest = to_oxygen(Strategy::BreadthFirst);
+        assert_eq!(236, shortest.path.len());
+
+        let some = to_oxygen(Strategy::DepthFirst);
+        assert_eq!(position(&shortest.path), position(&some.path));
+        assert!(some.path.len() >= 236);
+
+        // flood the whole area from the oxygen system
+        let mut minutes = HashMap::new();
+        let none = explore(
+            Strategy::BreadthFirst,
+            shortest.state,
+            Position::ZERO,
+            Direction::ALL.to_vec(),
+            |state, path| {
+                if move_droid(state, *path.last().unwrap())? == 0 {
+                    return Ok(Visit::Prune);
+                }
+
+                let pos = position(path);
+                minutes.entry(pos).or_insert_with(|| path.len());
+                Ok(Visit::Expand {
+                    key: pos,
+                    actions: Direction::ALL.to_vec(),
+                })
+            },
+        )
+        .unwrap();
+
+        assert!(none.is_none());
+        assert_eq!(Some(&368), minutes.values().max());
+    }
+}
@@ -0,0 +1,154 @@
+//! Additional opcodes for extended Intcode dialects.
+//!
+//! An extension declares which of its parameters are read and which are written to, so that
+//! parameter modes are decoded like for the built-in instructions. The handler is called with
+//! the values of the read parameters, and the values it leaves in place of the write parameters
+//! (which start out as zero) are stored to their addresses. Afterwards, execution continues with
+//! the next instruction. A handler that returns an error fails the instruction with
+//! `ErrorKind::ExtensionFailed`, without writing anything.
+//!
+//! The disassembler, control-flow graph and decompiler only know the built-in instructions, and
+//! show registered opcodes as data.
+
+use super::memory::Memory;
+use super::word::Word;
+use super::State;
+use crate::result::{format_err, Result};
+use std::sync::Arc;
+
+/// Opcodes of the built-in instructions, which cannot be overridden.
+const BUILTIN: [i64; 10] = [1, 2, 3, 4, 5, 6, 7, 8, 9, 99];
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum Param {
+    Read,
+    Write,
+}
+
+type Handler<W> = Arc<dyn Fn(&mut [W]) -> Result<()> + Send + Sync>;
+
+#[derive(Clone)]
+pub struct Extension<W: Word = i64> {
+    pub params: Vec<Param>,
+    pub(super) handler: Handler<W>,
+}
+
+impl<W: Word> std::fmt::Debug for Extension<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        write!(f, "Extension {{ params: {:?} }}", self.params)
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Execute instructions with `opcode` by calling `handler`. There can be at most three
+    /// parameters, as the modes of further ones cannot be encoded.
+    ///
+    /// Handlers are shared with forks of the machine, and have to synchronize any state they
+    /// keep.
+    pub fn register_opcode<F>(&mut self, opcode: i64, params: &[Param], handler: F) -> Result<()>
+    where
+        F: Fn(&mut [M::Word]) -> Result<()> + Send + Sync + 'static,
+    {
+        if !(0..100).contains(&opcode) || BUILTIN.contains(&opcode) {
+            return Err(format_err!("Cannot register opcode {}", opcode));
+        }
+        if params.len() > 3 {
+            return Err(format_err!(
+                "Opcode {} has {} parameters, at most 3 are supported",
+                opcode,
+                params.len()
+            ));
+        }
+
+        self.extensions.insert(
+            opcode,
+            Extension {
+                params: params.to_vec(),
+                handler: Arc::new(handler),
+            },
+        );
+        Ok(())
+    }
+
+    pub fn unregister_opcode(&mut self, opcode: i64) {
+        self.extensions.remove(&opcode);
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::error::ErrorKind;
+    use crate::intcode::IntCodeResult;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_extensions() {
+        let program = vec![
+            109, 10, // ARB 10
+            21142, 7, 3, 0, // MAX #7, #3, [rb+0]
+            250, 0, // PRINT [rb+0]
+            99, 0, 0,
+        ];
+
+        let mut state = State::new(program);
+        state
+            .register_opcode(42, &[Param::Read, Param::Read, Param::Write], |p| {
+                p[2] = p[0].max(p[1]);
+                Ok(())
+            })
+            .unwrap();
+
+        let printed = Arc::new(Mutex::new(Vec::new()));
+        let log = printed.clone();
+        state
+            .register_opcode(50, &[Param::Read], move |p| {
+                log.lock().unwrap().push(p[0]);
+                Ok(())
+            })
+            .unwrap();
+
+        assert_eq!(IntCodeResult::Halt, state.run(&mut Vec::new()).unwrap());
+        assert_eq!(7, state.memory[10]);
+        assert_eq!(vec![7], *printed.lock().unwrap());
+
+        // the usual errors apply
+        state.restart(&[11142, 1, 2, 3]);
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::WriteInImmediateMode { param: 3 }, err.kind);
+
+        state.unregister_opcode(42);
+        state.restart(&[42, 0, 0, 0, 99]);
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::InvalidOpcode(42), err.kind);
+
+        // failing handlers stop the machine at the instruction
+        state
+            .register_opcode(44, &[Param::Read, Param::Write], |p| {
+                if p[0] < 0 {
+                    return Err(format_err!("Negative argument {}", p[0]));
+                }
+                p[1] = p[0] * 2;
+                Ok(())
+            })
+            .unwrap();
+        state.restart(&[1101, -1, 0, 9, 44, 9, 10, 99, 0, 0, 5]);
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(
+            ErrorKind::ExtensionFailed {
+                opcode: 44,
+                message: "Negative argument -1".to_string()
+            },
+            err.kind
+        );
+        assert_eq!((4, 44), (err.ic, err.instruction));
+        assert_eq!(5, state.memory[10]);
+
+        assert!(state.register_opcode(2, &[], |_| Ok(())).is_err());
+        assert!(state.register_opcode(100, &[], |_| Ok(())).is_err());
+        assert!(state
+            .register_opcode(43, &[Param::Read; 4], |_| Ok(()))
+            .is_err());
+    }
+}
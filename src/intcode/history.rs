@@ -0,0 +1,256 @@
+//! An undo log of executed instructions, allowing Intcode execution to be stepped backwards.
+
+use super::memory::Memory;
+use super::word::Word;
+use super::State;
+use std::collections::VecDeque;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+struct UndoEntry<W: Word> {
+    ic: usize,
+    relative_base: i64,
+    steps: usize,
+    memory_len: usize,
+    /// Overwritten memory cells with their previous values, in order of writing.
+    writes: Vec<(usize, W)>,
+    input: Option<W>,
+    output: Option<W>,
+}
+
+/// Describes an instruction that was undone.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Undone<W: Word = i64> {
+    /// Address of the undone instruction, which is now the instruction counter again.
+    pub ic: usize,
+    /// The consumed input, which was put back at the front of the input queue.
+    pub input: Option<W>,
+    /// The produced output. Outputs already handed to the caller cannot be taken back.
+    pub output: Option<W>,
+}
+
+impl<W: Word> Undone<W> {
+    pub fn is_io(&self) -> bool {
+        self.input.is_some() || self.output.is_some()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct History<W: Word = i64> {
+    /// Maximum number of instructions to remember, or `None` for no limit.
+    pub limit: Option<usize>,
+
+    entries: VecDeque<UndoEntry<W>>,
+    current: Option<UndoEntry<W>>,
+}
+
+impl<W: Word> History<W> {
+    pub fn new(limit: Option<usize>) -> Self {
+        History {
+            limit,
+            entries: VecDeque::new(),
+            current: None,
+        }
+    }
+
+    /// Number of instructions that can be undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(super) fn begin(&mut self, ic: usize, relative_base: i64, steps: usize, memory_len: usize) {
+        self.current = Some(UndoEntry {
+            ic,
+            relative_base,
+            steps,
+            memory_len,
+            writes: Vec::new(),
+            input: None,
+            output: None,
+        });
+    }
+
+    pub(super) fn record_write(&mut self, address: usize, old_value: W) {
+        if let Some(entry) = &mut self.current {
+            entry.writes.push((address, old_value));
+        }
+    }
+
+    pub(super) fn record_input(&mut self, value: W) {
+        if let Some(entry) = &mut self.current {
+            entry.input = Some(value);
+        }
+    }
+
+    pub(super) fn record_output(&mut self, value: W) {
+        if let Some(entry) = &mut self.current {
+            entry.output = Some(value);
+        }
+    }
+
+    pub(super) fn finish(&mut self) {
+        if let Some(entry) = self.current.take() {
+            self.entries.push_back(entry);
+
+            if let Some(limit) = self.limit {
+                while self.entries.len() > limit {
+                    self.entries.pop_front();
+                }
+            }
+        }
+    }
+
+    pub(super) fn abort(&mut self) {
+        self.current = None;
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Start recording an undo log of at most `limit` instructions.
+    pub fn enable_history(&mut self, limit: Option<usize>) {
+        self.history = Some(History::new(limit));
+    }
+
+    pub fn disable_history(&mut self) {
+        self.history = None;
+    }
+
+    /// Undo the last executed instruction, restoring memory, registers, consumed input and the
+    /// step count.
+    ///
+    /// The profile and coverage are not rolled back, and keep counting undone instructions.
+    ///
+    /// Returns `None` if there is nothing to undo.
+    pub fn step_back(&mut self) -> Option<Undone<M::Word>> {
+        let entry = self.history.as_mut()?.entries.pop_back()?;
+
+        for (address, old_value) in entry.writes.into_iter().rev() {
+            if let Some(detector) = self.loop_detector.as_mut() {
+                detector.record_write(address, &self.memory.read(address), &old_value);
+            }
+            self.memory.write(address, old_value);
+        }
+        self.memory.truncate(entry.memory_len);
+
+        if let Some(v) = entry.input.clone() {
+            self.inputs.push_front(v);
+        }
+
+        self.ic = entry.ic;
+        self.relative_base = entry.relative_base;
+        self.steps = entry.steps;
+        self.watch_hits.clear();
+        if let Some(detector) = self.loop_detector.as_mut() {
+            detector.reset();
+        }
+
+        Some(Undone {
+            ic: entry.ic,
+            input: entry.input,
+            output: entry.output,
+        })
+    }
+
+    /// Undo up to `n` instructions, returning the undone instructions, most recent first.
+    pub fn rewind(&mut self, n: usize) -> Vec<Undone<M::Word>> {
+        let mut out = Vec::new();
+        for _ in 0..n {
+            match self.step_back() {
+                Some(u) => out.push(u),
+                None => break,
+            }
+        }
+        out
+    }
+
+    /// Undo instructions up to and including the most recent one that consumed an input or
+    /// produced an output, returning the undone instructions, most recent first.
+    pub fn rewind_to_io(&mut self) -> Vec<Undone<M::Word>> {
+        let mut out = Vec::new();
+        while let Some(u) = self.step_back() {
+            let is_io = u.is_io();
+            out.push(u);
+
+            if is_io {
+                break;
+            }
+        }
+        out
+    }
+
+    pub(super) fn history_begin(&mut self) {
+        let (ic, relative_base, steps) = (self.ic, self.relative_base, self.steps);
+        let memory_len = self.memory.len();
+        if let Some(history) = self.history.as_mut() {
+            history.begin(ic, relative_base, steps, memory_len);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::memory::PagedMemory;
+    use crate::intcode::IntCodeResult;
+
+    #[test]
+    fn test_rewind() {
+        let program = assemble(
+            "
+                    IN [x]
+                    ARB #3
+                    MUL [x], #7, [x]
+                    ADD [x], #1, [y]
+                    OUT [y]
+                    HLT
+            x:      data 0
+            y:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program.clone());
+        state.enable_history(None);
+        state.inputs.push_back(6);
+
+        let mut outputs = Vec::new();
+        assert_eq!(IntCodeResult::Output, state.run(&mut outputs).unwrap());
+        assert_eq!(vec![43], outputs);
+        assert_eq!(5, state.history.as_ref().unwrap().len());
+        assert_eq!(5, state.steps);
+
+        let undone = state.rewind_to_io();
+        assert_eq!(1, undone.len());
+        assert_eq!(Some(43), undone[0].output);
+        assert_eq!(12, state.ic);
+
+        let undone = state.rewind(10);
+        assert_eq!(4, undone.len());
+        assert_eq!(Some(6), undone[3].input);
+        assert_eq!(program, state.memory);
+        assert_eq!((0, 0, 0), (state.ic, state.relative_base, state.steps));
+        assert_eq!(state.inputs, vec![6]);
+        assert_eq!(None, state.step_back());
+    }
+
+    #[test]
+    fn test_undo_growth() {
+        // a write past the end of memory grows it, which is undone as well
+        let program = assemble("ADD #1, #2, [1000]\nHLT").unwrap();
+        let mut state = State::with_memory(PagedMemory::from_program(program.clone()));
+        state.enable_history(None);
+
+        assert_eq!(IntCodeResult::Halt, state.run(&mut Vec::new()).unwrap());
+        assert_eq!((1001, 2), (state.memory.len(), state.steps));
+
+        assert_eq!(2, state.rewind(2).len());
+        assert_eq!((program.len(), 0), (state.memory.len(), state.steps));
+        assert_eq!(0, state.memory.read(1000));
+        assert_eq!(program, state.memory.to_vec());
+    }
+}
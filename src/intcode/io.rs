@@ -0,0 +1,289 @@
+//! Input sources and output sinks for Intcode machines.
+//!
+//! `State::run_with` drives a machine until it halts, taking inputs from an `Input` and passing
+//! outputs to an `Output` as they are produced. Sources and sinks are provided for queues,
+//! iterators, closures, channels and byte streams. A pair `(input, output)` is both, and a
+//! single value implementing both traits can react to outputs when asked for the next input.
+
+use super::memory::Memory;
+use super::{IntCodeResult, State};
+use crate::result::{format_err, Result};
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, Sender};
+
+pub trait Input {
+    /// The next input value, or `None` if there is none (yet).
+    fn next_input(&mut self) -> Result<Option<i64>>;
+}
+
+pub trait Output {
+    fn output(&mut self, value: i64) -> Result<()>;
+}
+
+impl<T: Input + ?Sized> Input for &mut T {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        (**self).next_input()
+    }
+}
+
+impl<T: Output + ?Sized> Output for &mut T {
+    fn output(&mut self, value: i64) -> Result<()> {
+        (**self).output(value)
+    }
+}
+
+impl<I: Input, O> Input for (I, O) {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        self.0.next_input()
+    }
+}
+
+impl<I, O: Output> Output for (I, O) {
+    fn output(&mut self, value: i64) -> Result<()> {
+        self.1.output(value)
+    }
+}
+
+impl Input for VecDeque<i64> {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        Ok(self.pop_front())
+    }
+}
+
+impl Output for VecDeque<i64> {
+    fn output(&mut self, value: i64) -> Result<()> {
+        self.push_back(value);
+        Ok(())
+    }
+}
+
+impl Output for Vec<i64> {
+    fn output(&mut self, value: i64) -> Result<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+/// Waits for the next value, and runs out of input once all senders are gone.
+impl Input for Receiver<i64> {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        Ok(self.recv().ok())
+    }
+}
+
+/// Discards values once the receiver is gone, as the machine reading them has stopped.
+impl Output for Sender<i64> {
+    fn output(&mut self, value: i64) -> Result<()> {
+        let _ = self.send(value);
+        Ok(())
+    }
+}
+
+/// Inputs taken from an iterator.
+#[derive(Debug, Clone)]
+pub struct Iter<I>(pub I);
+
+impl<I: Iterator<Item = i64>> Input for Iter<I> {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        Ok(self.0.next())
+    }
+}
+
+/// Inputs returned by a closure.
+pub struct FnInput<F>(pub F);
+
+impl<F: FnMut() -> Result<Option<i64>>> Input for FnInput<F> {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        (self.0)()
+    }
+}
+
+/// Outputs passed to a closure.
+pub struct FnOutput<F>(pub F);
+
+impl<F: FnMut(i64) -> Result<()>> Output for FnOutput<F> {
+    fn output(&mut self, value: i64) -> Result<()> {
+        (self.0)(value)
+    }
+}
+
+/// Inputs read from a byte stream, one byte per value.
+#[derive(Debug)]
+pub struct Reader<R>(pub R);
+
+impl<R: std::io::Read> Input for Reader<R> {
+    fn next_input(&mut self) -> Result<Option<i64>> {
+        let mut buf = [0];
+        Ok(match self.0.read(&mut buf)? {
+            0 => None,
+            _ => Some(i64::from(buf[0])),
+        })
+    }
+}
+
+/// Outputs written to a byte stream, one byte per value.
+#[derive(Debug)]
+pub struct Writer<W>(pub W);
+
+impl<W: std::io::Write> Output for Writer<W> {
+    fn output(&mut self, value: i64) -> Result<()> {
+        if !(0..=255).contains(&value) {
+            return Err(format_err!("Output is not a byte: {}", value));
+        }
+
+        self.0.write_all(&[value as u8])?;
+        Ok(())
+    }
+}
+
+impl<M: Memory<Word = i64>> State<M> {
+    /// Run until the program halts, passing outputs to `io` and asking it for input whenever
+    /// the input queue is empty.
+    ///
+    /// Returns `IntCodeResult::Input` if `io` runs out of input, after which the machine can be
+    /// resumed. Watchpoint hits, exhausted budgets and infinite loops are returned as well.
+    pub fn run_with<T: Input + Output>(&mut self, mut io: T) -> Result<IntCodeResult> {
+        let mut outputs = Vec::new();
+        loop {
+            match self.run(&mut outputs)? {
+                IntCodeResult::Output => {
+                    for v in outputs.drain(..) {
+                        io.output(v)?;
+                    }
+                }
+                IntCodeResult::Input => match io.next_input()? {
+                    Some(v) => self.inputs.push_back(v),
+                    None => return Ok(IntCodeResult::Input),
+                },
+                res => return Ok(res),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use std::sync::mpsc::channel;
+
+    /// Add up inputs until reading a zero, printing the running sum.
+    fn summer() -> Vec<i64> {
+        assemble(
+            "
+            loop:   IN [v]
+                    JF [v], #end
+                    ADD [sum], [v], [sum]
+                    OUT [sum]
+                    JT #1, #loop
+            end:    HLT
+            v:      data 0
+            sum:    data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_run_with() {
+        let mut state = State::new(summer());
+        let mut outputs = Vec::new();
+
+        let res = state.run_with((Iter(vec![1, 2].into_iter()), &mut outputs));
+        assert_eq!(IntCodeResult::Input, res.unwrap());
+        assert_eq!(vec![1, 3], outputs);
+
+        // resume where the machine stopped
+        let mut inputs: VecDeque<i64> = vec![3, 0].into();
+        let res = state.run_with((&mut inputs, &mut outputs));
+        assert_eq!(IntCodeResult::Halt, res.unwrap());
+        assert_eq!(vec![1, 3, 6], outputs);
+        assert!(inputs.is_empty());
+    }
+
+    #[test]
+    fn test_feedback() {
+        // a single value implementing both traits sees the outputs before giving inputs
+        struct Doubler {
+            last: i64,
+            outputs: usize,
+        }
+
+        impl Input for Doubler {
+            fn next_input(&mut self) -> Result<Option<i64>> {
+                Ok(Some(if self.outputs < 4 { self.last } else { 0 }))
+            }
+        }
+
+        impl Output for Doubler {
+            fn output(&mut self, value: i64) -> Result<()> {
+                self.last = value;
+                self.outputs += 1;
+                Ok(())
+            }
+        }
+
+        let mut doubler = Doubler {
+            last: 1,
+            outputs: 0,
+        };
+        let mut state = State::new(summer());
+        assert_eq!(IntCodeResult::Halt, state.run_with(&mut doubler).unwrap());
+        assert_eq!(8, doubler.last);
+    }
+
+    #[test]
+    fn test_closures_and_errors() {
+        let mut state = State::new(summer());
+        let mut n = 0;
+        let io = (
+            FnInput(|| {
+                n += 1;
+                Ok(Some(n))
+            }),
+            FnOutput(|v| match v {
+                v if v > 10 => Err(format_err!("Too large: {}", v)),
+                _ => Ok(()),
+            }),
+        );
+
+        let err = state.run_with(io).unwrap_err();
+        assert_eq!("Too large: 15", err.to_string());
+    }
+
+    #[test]
+    fn test_channels() {
+        let (input_tx, input_rx) = channel();
+        let (output_tx, output_rx) = channel();
+
+        let handle = std::thread::spawn(move || {
+            let mut state = State::new(summer());
+            state.run_with((input_rx, output_tx)).unwrap()
+        });
+
+        for v in 1..=3 {
+            input_tx.send(v).unwrap();
+        }
+        drop(input_tx);
+
+        assert_eq!(vec![1, 3, 6], output_rx.iter().collect::<Vec<_>>());
+        assert_eq!(IntCodeResult::Input, handle.join().unwrap());
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut state =
+            State::new(assemble("loop: IN [v]\nOUT [v]\nJT #1, #loop\nv: data 0").unwrap());
+        let mut out = Vec::new();
+
+        let res = state.run_with((Reader(&b"hello"[..]), Writer(&mut out)));
+        assert_eq!(IntCodeResult::Input, res.unwrap());
+        assert_eq!(b"hello".to_vec(), out);
+
+        state.inputs.push_back(300);
+        assert!(state
+            .run_with((Reader(&b""[..]), Writer(&mut out)))
+            .is_err());
+    }
+}
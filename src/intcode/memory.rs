@@ -0,0 +1,275 @@
+//! Storage backends for the memory of an Intcode machine.
+//!
+//! Memory is conceptually infinite and zero-initialized. Its length is one past the highest
+//! address that was ever written (or the length of the loaded program), and only matters for
+//! fetching instructions and validating jumps.
+
+use super::word::Word;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub trait Memory {
+    type Word: Word;
+
+    fn from_program(program: Vec<Self::Word>) -> Self
+    where
+        Self: Sized;
+
+    /// Read a word. Addresses past the end of memory read as zero.
+    fn read(&self, address: usize) -> Self::Word;
+
+    /// Write a word, growing memory if needed.
+    fn write(&mut self, address: usize, value: Self::Word);
+
+    /// Shrink memory to `len` words. The words past it read as zero afterwards.
+    fn truncate(&mut self, len: usize);
+
+    fn len(&self) -> usize;
+
+    /// Read a word that lies within memory.
+    fn fetch(&self, address: usize) -> Option<Self::Word> {
+        if address < self.len() {
+            Some(self.read(address))
+        } else {
+            None
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Copy out the words from address 0 up to the end of memory.
+    fn to_vec(&self) -> Vec<Self::Word> {
+        (0..self.len()).map(|a| self.read(a)).collect()
+    }
+
+    /// The allocated parts of memory as start addresses and their words, in order of address.
+    /// They end at the end of memory, and all other words are zero.
+    fn regions(&self) -> Vec<(usize, &[Self::Word])>;
+}
+
+/// Dense memory, storing every word up to the highest address written.
+impl<W: Word> Memory for Vec<W> {
+    type Word = W;
+
+    fn from_program(program: Vec<W>) -> Self {
+        program
+    }
+
+    fn read(&self, address: usize) -> W {
+        self.get(address).cloned().unwrap_or_else(|| W::from_i64(0))
+    }
+
+    fn write(&mut self, address: usize, value: W) {
+        if self.len() <= address {
+            self.resize(address + 1, W::from_i64(0));
+        }
+
+        self[address] = value;
+    }
+
+    fn truncate(&mut self, len: usize) {
+        Vec::truncate(self, len);
+    }
+
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn to_vec(&self) -> Vec<W> {
+        self.clone()
+    }
+
+    fn regions(&self) -> Vec<(usize, &[W])> {
+        if self.is_empty() {
+            Vec::new()
+        } else {
+            vec![(0, &self[..])]
+        }
+    }
+}
+
+pub const PAGE_SIZE: usize = 1024;
+
+type Page<W> = Arc<Vec<W>>;
+
+/// Sparse memory made up of fixed-size pages that are allocated when first written to.
+///
+/// Clones share their pages, which are copied when they are first written to by either side,
+/// so that cloning is cheap even for large memories.
+#[derive(Debug, Clone)]
+pub struct PagedMemory<W: Word = i64> {
+    pages: HashMap<usize, Page<W>>,
+    len: usize,
+}
+
+impl<W: Word> Default for PagedMemory<W> {
+    fn default() -> Self {
+        PagedMemory {
+            pages: HashMap::new(),
+            len: 0,
+        }
+    }
+}
+
+impl<W: Word> PagedMemory<W> {
+    pub fn new() -> Self {
+        PagedMemory::default()
+    }
+
+    /// Number of allocated pages.
+    pub fn n_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Number of allocated pages that are shared with a clone.
+    pub fn n_shared_pages(&self) -> usize {
+        self.pages
+            .values()
+            .filter(|p| Arc::strong_count(p) > 1)
+            .count()
+    }
+}
+
+impl<W: Word> Memory for PagedMemory<W> {
+    type Word = W;
+
+    fn from_program(program: Vec<W>) -> Self {
+        let mut mem = PagedMemory::new();
+        for (address, value) in program.into_iter().enumerate() {
+            mem.write(address, value);
+        }
+        mem
+    }
+
+    fn read(&self, address: usize) -> W {
+        match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => page[address % PAGE_SIZE].clone(),
+            None => W::from_i64(0),
+        }
+    }
+
+    fn write(&mut self, address: usize, value: W) {
+        let page = self
+            .pages
+            .entry(address / PAGE_SIZE)
+            .or_insert_with(|| Arc::new(vec![W::from_i64(0); PAGE_SIZE]));
+
+        Arc::make_mut(page)[address % PAGE_SIZE] = value;
+
+        if address >= self.len {
+            self.len = address + 1;
+        }
+    }
+
+    fn truncate(&mut self, len: usize) {
+        if len >= self.len {
+            return;
+        }
+
+        self.pages.retain(|i, _| i * PAGE_SIZE < len);
+        if let Some(page) = self.pages.get_mut(&(len / PAGE_SIZE)) {
+            let zero = W::from_i64(0);
+            if page[len % PAGE_SIZE..].iter().any(|v| *v != zero) {
+                for v in &mut Arc::make_mut(page)[len % PAGE_SIZE..] {
+                    *v = zero.clone();
+                }
+            }
+        }
+        self.len = len;
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn regions(&self) -> Vec<(usize, &[W])> {
+        let mut pages: Vec<_> = self.pages.iter().collect();
+        pages.sort_by_key(|(i, _)| **i);
+
+        pages
+            .into_iter()
+            .map(|(i, page)| {
+                let start = i * PAGE_SIZE;
+                let end = PAGE_SIZE.min(self.len - start);
+                (start, &page[..end])
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::{parse_program, IntCodeResult, State};
+    use crate::util::read_to_string;
+
+    fn run_to_halt<M: Memory<Word = i64>>(mut state: State<M>, inputs: Vec<i64>) -> Vec<i64> {
+        state.inputs = inputs.into();
+        let mut outputs = Vec::new();
+        while state.run(&mut outputs).unwrap() != IntCodeResult::Halt {}
+        outputs
+    }
+
+    #[test]
+    fn test_paged_memory() {
+        let mut mem: PagedMemory = PagedMemory::from_program(vec![1, 2, 3]);
+        assert_eq!((3, 1), (mem.len(), mem.n_pages()));
+
+        mem.write(1_000_000_000, 42);
+        assert_eq!(42, mem.read(1_000_000_000));
+        assert_eq!(0, mem.read(1_000_000_001));
+        assert_eq!((1_000_000_001, 2), (mem.len(), mem.n_pages()));
+
+        let regions = mem.regions();
+        assert_eq!(
+            vec![0, 999_999_488],
+            regions.iter().map(|r| r.0).collect::<Vec<_>>()
+        );
+        assert_eq!((PAGE_SIZE, 513), (regions[0].1.len(), regions[1].1.len()));
+        assert_eq!(&42, regions[1].1.last().unwrap());
+    }
+
+    #[test]
+    fn test_copy_on_write() {
+        let mut mem: PagedMemory = PagedMemory::from_program(vec![1, 2, 3]);
+        mem.write(5 * PAGE_SIZE, 4);
+
+        let mut copy = mem.clone();
+        assert_eq!(2, copy.n_shared_pages());
+
+        copy.write(1, 7);
+        assert_eq!((2, 7), (mem.read(1), copy.read(1)));
+        assert_eq!((1, 1), (mem.n_shared_pages(), copy.n_shared_pages()));
+
+        drop(copy);
+        assert_eq!(0, mem.n_shared_pages());
+    }
+
+    #[test]
+    fn test_backends_agree() {
+        let program = parse_program(&read_to_string("data/day09/input").unwrap()).unwrap();
+
+        let dense = run_to_halt(State::new(program.clone()), vec![1]);
+        let paged = run_to_halt(
+            State::with_memory(PagedMemory::from_program(program)),
+            vec![1],
+        );
+
+        assert_eq!(dense, paged);
+    }
+
+    #[test]
+    fn test_high_address() {
+        // write to a high address, read it back and output it
+        let program: Vec<i64> = vec![1101, 3, 4, 1 << 40, 4, 1 << 40, 99];
+
+        let outputs = run_to_halt(
+            State::with_memory(PagedMemory::from_program(program)),
+            vec![],
+        );
+        assert_eq!(vec![7], outputs);
+    }
+}
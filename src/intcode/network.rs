@@ -0,0 +1,451 @@
+//! Simulation of packet networks of Intcode machines, such as the one of day 23.
+//!
+//! Every node is an Intcode machine. Its outputs are cut into packets by a `Framing`, and
+//! packets sent to a node are encoded into words on its input queue. A node that waits for input
+//! while its queue is empty reads an idle value instead. Addresses that do not belong to a node
+//! can be served by `Device`s, e.g. a NAT.
+//!
+//! Nodes take turns in rounds. A turn lasts until the node waits for input a second time with
+//! an empty queue, halts, or has used up its quantum. The network is idle once every node spent
+//! a whole round waiting without receiving or sending anything, at which point the devices are
+//! notified.
+
+use super::{IntCodeResult, State};
+use crate::result::{format_err, Result};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::rc::Rc;
+
+/// Number of instructions a node executes before the next node gets its turn.
+const QUANTUM: usize = 10_000;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Packet {
+    pub source: i64,
+    pub destination: i64,
+    pub payload: Vec<i64>,
+}
+
+/// How packets are written to a node's output and read from its input.
+pub trait Framing {
+    /// Take a complete packet sent by node `source` off the front of its unprocessed outputs.
+    fn decode(&self, source: i64, outputs: &mut VecDeque<i64>) -> Option<Packet>;
+
+    /// The words a node reads when it receives `packet`.
+    fn encode(&self, packet: &Packet) -> Vec<i64>;
+}
+
+/// A destination address followed by a fixed number of payload words, of which only the
+/// payload is delivered. Day 23 uses a payload of two words.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct AddressedWords {
+    pub payload_len: usize,
+}
+
+impl Framing for AddressedWords {
+    fn decode(&self, source: i64, outputs: &mut VecDeque<i64>) -> Option<Packet> {
+        if outputs.len() < 1 + self.payload_len {
+            return None;
+        }
+
+        let destination = outputs.pop_front()?;
+        let payload = outputs.drain(..self.payload_len).collect();
+        Some(Packet {
+            source,
+            destination,
+            payload,
+        })
+    }
+
+    fn encode(&self, packet: &Packet) -> Vec<i64> {
+        packet.payload.clone()
+    }
+}
+
+/// Collects packets sent and requests made by a device.
+#[derive(Debug, Default)]
+pub struct Outbox {
+    packets: Vec<Packet>,
+    stop: bool,
+}
+
+impl Outbox {
+    pub fn send(&mut self, packet: Packet) {
+        self.packets.push(packet);
+    }
+
+    /// End the simulation after the current turn.
+    pub fn stop(&mut self) {
+        self.stop = true;
+    }
+}
+
+/// A handler for packets sent to an address that does not belong to a node.
+pub trait Device {
+    fn receive(&mut self, packet: Packet, outbox: &mut Outbox) -> Result<()>;
+
+    /// Called when the network has become idle.
+    fn idle(&mut self, _outbox: &mut Outbox) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Lets the caller keep access to a device after adding it to a network.
+impl<D: Device> Device for Rc<RefCell<D>> {
+    fn receive(&mut self, packet: Packet, outbox: &mut Outbox) -> Result<()> {
+        self.borrow_mut().receive(packet, outbox)
+    }
+
+    fn idle(&mut self, outbox: &mut Outbox) -> Result<()> {
+        self.borrow_mut().idle(outbox)
+    }
+}
+
+/// The order in which nodes take their turns within a round.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Schedule {
+    /// By increasing address.
+    RoundRobin,
+    /// In a pseudo-random order, different in every round, determined by the seed.
+    Random { seed: u64 },
+}
+
+/// Why a simulation ended.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Outcome {
+    /// A device asked to stop.
+    Stopped,
+    /// All nodes halted.
+    Halted,
+    /// The network is idle, and no device sent anything when notified.
+    Idle,
+}
+
+#[derive(Debug)]
+pub struct Node {
+    pub state: State,
+    /// Packets sent by the node, in order.
+    pub sent: Vec<Packet>,
+    /// Packets delivered to the node, in order.
+    pub received: Vec<Packet>,
+    pub halted: bool,
+    /// Outputs that do not form a complete packet yet.
+    pending: VecDeque<i64>,
+}
+
+pub struct Network<F: Framing> {
+    pub nodes: Vec<Node>,
+    pub framing: F,
+    /// The value read by nodes waiting for input with an empty queue.
+    pub idle_input: i64,
+    pub schedule: Schedule,
+    devices: BTreeMap<i64, Box<dyn Device>>,
+    rng: u64,
+}
+
+impl<F: Framing> Network<F> {
+    pub fn new(framing: F) -> Self {
+        Network {
+            nodes: Vec::new(),
+            framing,
+            idle_input: -1,
+            schedule: Schedule::RoundRobin,
+            devices: BTreeMap::new(),
+            rng: 0,
+        }
+    }
+
+    /// A network of `n` nodes running `program`, each of which first reads its own address.
+    pub fn with_nodes(program: &[i64], n: usize, framing: F) -> Self {
+        let mut network = Network::new(framing);
+        for address in 0..n {
+            let mut state = State::new(program.to_vec());
+            state.inputs.push_back(address as i64);
+            network.add_node(state);
+        }
+        network
+    }
+
+    /// Add a node, returning its address.
+    pub fn add_node(&mut self, state: State) -> i64 {
+        self.nodes.push(Node {
+            state,
+            sent: Vec::new(),
+            received: Vec::new(),
+            halted: false,
+            pending: VecDeque::new(),
+        });
+        self.nodes.len() as i64 - 1
+    }
+
+    /// Serve packets sent to `address` with `device`.
+    pub fn add_device<D: Device + 'static>(&mut self, address: i64, device: D) {
+        self.devices.insert(address, Box::new(device));
+    }
+
+    /// Deliver `packets`, and any packets that devices send in response. Returns whether a
+    /// device asked to stop.
+    fn deliver(&mut self, mut packets: VecDeque<Packet>) -> Result<bool> {
+        let mut stop = false;
+
+        while let Some(packet) = packets.pop_front() {
+            let destination = packet.destination;
+
+            if let Some(device) = self.devices.get_mut(&destination) {
+                let mut outbox = Outbox::default();
+                device.receive(packet, &mut outbox)?;
+                packets.extend(outbox.packets);
+                stop |= outbox.stop;
+            } else if destination >= 0 && (destination as usize) < self.nodes.len() {
+                let node = &mut self.nodes[destination as usize];
+                node.state.inputs.extend(self.framing.encode(&packet));
+                node.received.push(packet);
+            } else {
+                return Err(format_err!(
+                    "Packet from {} to unknown address {}",
+                    packet.source,
+                    destination
+                ));
+            }
+        }
+
+        Ok(stop)
+    }
+
+    /// Give node `id` its turn. Returns whether it received, sent or computed anything, and the
+    /// packets it sent.
+    fn turn(&mut self, id: usize) -> Result<(bool, Vec<Packet>)> {
+        let node = &mut self.nodes[id];
+        if node.halted {
+            return Ok((false, Vec::new()));
+        }
+
+        let mut active = !node.state.inputs.is_empty();
+        let mut waited = false;
+        let mut outputs = Vec::new();
+        let mut packets = Vec::new();
+
+        for _ in 0..QUANTUM {
+            match node.state.step(&mut outputs)? {
+                None | Some(IntCodeResult::Watch(_)) | Some(IntCodeResult::BudgetExhausted) => {}
+                Some(IntCodeResult::Output) => {
+                    active = true;
+                    node.pending.extend(outputs.drain(..));
+                    while let Some(p) = self.framing.decode(id as i64, &mut node.pending) {
+                        node.sent.push(p.clone());
+                        packets.push(p);
+                    }
+                }
+                Some(IntCodeResult::Input) if waited => return Ok((active, packets)),
+                Some(IntCodeResult::Input) => {
+                    node.state.inputs.push_back(self.idle_input);
+                    waited = true;
+                }
+                Some(IntCodeResult::Halt) => {
+                    node.halted = true;
+                    return Ok((active, packets));
+                }
+                Some(IntCodeResult::InfiniteLoop) => {
+                    return Err(format_err!("Node {} is stuck in an infinite loop", id))
+                }
+            }
+        }
+
+        // still computing
+        Ok((true, packets))
+    }
+
+    /// The order of the turns in the next round.
+    fn order(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.nodes.len()).collect();
+
+        if let Schedule::Random { seed } = self.schedule {
+            if self.rng == 0 {
+                self.rng = seed | 1;
+            }
+
+            // Fisher-Yates shuffle driven by xorshift64
+            for i in (1..order.len()).rev() {
+                self.rng ^= self.rng << 13;
+                self.rng ^= self.rng >> 7;
+                self.rng ^= self.rng << 17;
+                order.swap(i, (self.rng % (i as u64 + 1)) as usize);
+            }
+        }
+
+        order
+    }
+
+    /// Run the network until a device asks to stop, all nodes halted, or the network stays
+    /// idle.
+    pub fn run(&mut self) -> Result<Outcome> {
+        loop {
+            let mut active = false;
+
+            for id in self.order() {
+                let (a, packets) = self.turn(id)?;
+                active |= a;
+
+                if self.deliver(packets.into())? {
+                    return Ok(Outcome::Stopped);
+                }
+            }
+
+            if self.nodes.iter().all(|n| n.halted) {
+                return Ok(Outcome::Halted);
+            }
+            if active {
+                continue;
+            }
+
+            let mut outbox = Outbox::default();
+            for device in self.devices.values_mut() {
+                device.idle(&mut outbox)?;
+            }
+
+            if outbox.packets.is_empty() && !outbox.stop {
+                return Ok(Outcome::Idle);
+            }
+            if self.deliver(outbox.packets.into())? || outbox.stop {
+                return Ok(Outcome::Stopped);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::parse_program;
+    use crate::util::read_to_string;
+
+    /// The NAT of day 23, stopping once it sends the same `y` twice in a row.
+    #[derive(Default)]
+    struct Nat {
+        last: Option<Packet>,
+        first_y: Option<i64>,
+        sent_y: Option<i64>,
+    }
+
+    impl Device for Nat {
+        fn receive(&mut self, packet: Packet, _outbox: &mut Outbox) -> Result<()> {
+            self.first_y = self.first_y.or(Some(packet.payload[1]));
+            self.last = Some(packet);
+            Ok(())
+        }
+
+        fn idle(&mut self, outbox: &mut Outbox) -> Result<()> {
+            let packet = self
+                .last
+                .clone()
+                .ok_or_else(|| format_err!("No packet to resend"))?;
+            let y = packet.payload[1];
+
+            if self.sent_y == Some(y) {
+                outbox.stop();
+            } else {
+                self.sent_y = Some(y);
+                outbox.send(Packet {
+                    source: 255,
+                    destination: 0,
+                    ..packet
+                });
+            }
+            Ok(())
+        }
+    }
+
+    fn day23(schedule: Schedule) -> (Option<i64>, Option<i64>) {
+        let program = parse_program(&read_to_string("data/day23/input").unwrap()).unwrap();
+        let mut network = Network::with_nodes(&program, 50, AddressedWords { payload_len: 2 });
+        network.schedule = schedule;
+
+        let nat = Rc::new(RefCell::new(Nat::default()));
+        network.add_device(255, nat.clone());
+        assert_eq!(Outcome::Stopped, network.run().unwrap());
+
+        let nat = nat.borrow();
+        (nat.first_y, nat.sent_y)
+    }
+
+    #[test]
+    fn test_day23() {
+        let expected = day23(Schedule::RoundRobin);
+        assert_eq!((Some(14834), Some(10215)), expected);
+
+        for seed in 1..3 {
+            assert_eq!(expected, day23(Schedule::Random { seed }));
+        }
+    }
+
+    /// Sends every payload back to its source until it reaches 5, starting once the network is
+    /// idle for the first time.
+    #[derive(Default)]
+    struct Echo {
+        started: bool,
+    }
+
+    impl Device for Echo {
+        fn receive(&mut self, packet: Packet, outbox: &mut Outbox) -> Result<()> {
+            if packet.payload[0] < 5 {
+                outbox.send(Packet {
+                    source: 9,
+                    destination: packet.source,
+                    payload: packet.payload,
+                });
+            }
+            Ok(())
+        }
+
+        fn idle(&mut self, outbox: &mut Outbox) -> Result<()> {
+            if !self.started {
+                self.started = true;
+                outbox.send(Packet {
+                    source: 9,
+                    destination: 0,
+                    payload: vec![0],
+                });
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_idle_and_logs() {
+        // sends every value it receives to address 9, incremented
+        let program = assemble(
+            "
+                    IN [me]
+            loop:   IN [v]
+                    EQ [v], #-1, [t]
+                    JT [t], #loop
+                    ADD [v], #1, [v]
+                    OUT #9
+                    OUT [v]
+                    JT #1, #loop
+            me:     data 0
+            v:      data 0
+            t:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut network = Network::with_nodes(&program, 2, AddressedWords { payload_len: 1 });
+        network.add_device(9, Echo::default());
+        assert_eq!(Outcome::Idle, network.run().unwrap());
+
+        let payloads =
+            |packets: &[Packet]| packets.iter().map(|p| p.payload[0]).collect::<Vec<_>>();
+        assert_eq!(vec![0, 1, 2, 3, 4], payloads(&network.nodes[0].received));
+        assert_eq!(vec![1, 2, 3, 4, 5], payloads(&network.nodes[0].sent));
+        assert!(network.nodes[1].received.is_empty());
+        assert!(network.nodes[1].sent.is_empty());
+
+        // without the device, the first packet goes nowhere
+        let mut network = Network::with_nodes(&program, 1, AddressedWords { payload_len: 1 });
+        network.nodes[0].state.inputs.push_back(0);
+        assert!(network.run().is_err());
+    }
+}
@@ -0,0 +1,180 @@
+//! Execution profiles of Intcode programs.
+//!
+//! A profile counts how often each address was executed, how often each opcode ran, how often
+//! each memory cell was read and written, and how many values were consumed and produced.
+
+use super::disasm::{decode_with, Opcode};
+use super::memory::Memory;
+use super::word::Word;
+use super::State;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Profile {
+    /// Number of instructions executed, by the address they start at.
+    pub executions: HashMap<usize, u64>,
+    /// Number of instructions executed, by opcode.
+    pub opcodes: BTreeMap<i64, u64>,
+    pub reads: HashMap<usize, u64>,
+    pub writes: HashMap<usize, u64>,
+    pub inputs: u64,
+    pub outputs: u64,
+}
+
+/// Sort counts by decreasing count, then by increasing address, and keep the first `limit`.
+fn hottest(counts: &HashMap<usize, u64>, limit: usize) -> Vec<(usize, u64)> {
+    let mut out: Vec<(usize, u64)> = counts.iter().map(|(&a, &n)| (a, n)).collect();
+    out.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    out.truncate(limit);
+    out
+}
+
+fn percent(n: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * n as f64 / total as f64
+    }
+}
+
+impl Profile {
+    pub fn new() -> Self {
+        Profile::default()
+    }
+
+    pub(super) fn record_execution(&mut self, address: usize, opcode: i64) {
+        *self.executions.entry(address).or_insert(0) += 1;
+        *self.opcodes.entry(opcode).or_insert(0) += 1;
+    }
+
+    pub(super) fn record_read(&mut self, address: usize) {
+        *self.reads.entry(address).or_insert(0) += 1;
+    }
+
+    pub(super) fn record_write(&mut self, address: usize) {
+        *self.writes.entry(address).or_insert(0) += 1;
+    }
+
+    pub(super) fn record_input(&mut self) {
+        self.inputs += 1;
+    }
+
+    pub(super) fn record_output(&mut self) {
+        self.outputs += 1;
+    }
+
+    /// Total number of instructions executed.
+    pub fn total(&self) -> u64 {
+        self.opcodes.values().sum()
+    }
+
+    /// Render the I/O counts, the `limit` most executed addresses, the opcode mix and the `limit`
+    /// most accessed memory cells. If `fetch` is given, hot spots are annotated with the
+    /// instruction found at the address.
+    pub fn report<F: Fn(usize) -> Option<i64>>(&self, limit: usize, fetch: Option<F>) -> String {
+        let mut out = String::new();
+        let w = &mut out;
+        let total = self.total();
+
+        let _ = writeln!(w, "{} instructions executed", total);
+        let _ = writeln!(w, "{} inputs, {} outputs", self.inputs, self.outputs);
+
+        let _ = writeln!(w, "\nHot spots:");
+        for (address, n) in hottest(&self.executions, limit) {
+            let _ = write!(w, "{:12} {:6.2}% {:5}", n, percent(n, total), address);
+            if let Some(fetch) = fetch.as_ref() {
+                let _ = write!(w, ": {}", decode_with(fetch, address));
+            }
+            let _ = writeln!(w);
+        }
+
+        let _ = writeln!(w, "\nOpcodes:");
+        let mut opcodes: Vec<(i64, u64)> = self.opcodes.iter().map(|(&o, &n)| (o, n)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for (code, n) in opcodes {
+            let name = match Opcode::from_code(code) {
+                Some(o) => o.mnemonic().to_string(),
+                None => code.to_string(),
+            };
+            let _ = writeln!(w, "{:12} {:6.2}% {}", n, percent(n, total), name);
+        }
+
+        for (title, counts) in &[("Most read:", &self.reads), ("Most written:", &self.writes)] {
+            let _ = writeln!(w, "\n{}", title);
+            for (address, n) in hottest(counts, limit) {
+                let _ = writeln!(w, "{:12} [{}]", n, address);
+            }
+        }
+
+        out
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Start collecting a new execution profile in `State::profile`.
+    pub fn enable_profiling(&mut self) {
+        self.profile = Some(Profile::new());
+    }
+
+    /// Stop profiling, returning the collected profile.
+    pub fn disable_profiling(&mut self) -> Option<Profile> {
+        self.profile.take()
+    }
+
+    /// Render a report of the collected profile, annotated with the disassembly of the
+    /// current memory if `annotate` is set.
+    pub fn profile_report(&self, limit: usize, annotate: bool) -> Option<String> {
+        let memory = &self.memory;
+        let fetch = |a| memory.fetch(a).and_then(|w| w.to_i64());
+        let profile = self.profile.as_ref()?;
+
+        Some(profile.report(limit, if annotate { Some(fetch) } else { None }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::IntCodeResult;
+
+    #[test]
+    fn test_profile() {
+        // output 3, 2, 1
+        let program = assemble(
+            "
+                    IN [n]
+            loop:   OUT [n]
+                    ADD [n], #-1, [n]
+                    JT [n], #loop
+                    HLT
+            n:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program);
+        state.enable_profiling();
+        state.inputs.push_back(3);
+
+        let mut outputs = Vec::new();
+        while state.run(&mut outputs).unwrap() != IntCodeResult::Halt {}
+        assert_eq!(vec![3, 2, 1], outputs);
+
+        let profile = state.profile.as_ref().unwrap();
+        assert_eq!(11, profile.total());
+        assert_eq!(1, profile.inputs);
+        assert_eq!(3, profile.outputs);
+        assert_eq!(Some(&3), profile.executions.get(&2));
+        assert_eq!(Some(&1), profile.executions.get(&11));
+        assert_eq!(Some(&3), profile.opcodes.get(&4));
+        assert_eq!(Some(&9), profile.reads.get(&12));
+        assert_eq!(Some(&4), profile.writes.get(&12));
+
+        let report = state.profile_report(2, true).unwrap();
+        assert!(report.starts_with("11 instructions executed\n1 inputs, 3 outputs\n"));
+        assert!(report.contains("           3  27.27%     2: OUT [12]\n"));
+    }
+}
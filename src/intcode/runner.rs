@@ -0,0 +1,488 @@
+//! Running several Intcode machines that talk to each other through channels.
+//!
+//! Every machine reads its input from a `std::sync::mpsc::Receiver` and passes its outputs to
+//! an `io::Output`, usually the `Sender` of another machine's input channel. `Threaded` runs each
+//! machine on its own OS thread, while `Deterministic` interleaves them on the calling thread in
+//! a fixed order, so that tests are reproducible. Both implement `Runner`.
+//!
+//! A machine stops once it waits for input that can no longer arrive, i.e. its input channel is
+//! empty and all senders are gone. A machine that halts or stops drops its output, which closes
+//! the input of the machine it feeds, so a halting machine shuts down the machines downstream of
+//! it once they have processed everything it sent. Outputs sent to a machine that has already
+//! stopped are discarded, so e.g. a feedback loop shuts down in order once its first machine
+//! halts. All machines can also be stopped through a `Shutdown` handle, e.g. from an output sink
+//! that has seen the result it was waiting for.
+
+use super::io::Output;
+use super::{IntCodeResult, State};
+use crate::result::{format_err, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Number of instructions a machine executes before checking for shutdown, or before the
+/// deterministic scheduler moves on to the next machine.
+const QUANTUM: usize = 10_000;
+
+/// How long a blocked machine thread waits for input before checking for shutdown.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// What a machine does when it needs input and its channel is empty.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum InputMode {
+    /// Wait for the next value.
+    Blocking,
+    /// Read `default` instead, as the network interfaces of day 23 read -1.
+    NonBlocking { default: i64 },
+}
+
+/// Why a machine stopped running.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Exit {
+    Halted,
+    /// The machine waited for input after its input channel was closed.
+    Closed,
+    /// The machine was stopped through a `Shutdown` handle.
+    Stopped,
+}
+
+/// A handle to stop all machines of a runner.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown(Arc<AtomicBool>);
+
+impl Shutdown {
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// A machine that was run to completion.
+#[derive(Debug)]
+pub struct Finished {
+    pub state: State,
+    pub exit: Exit,
+}
+
+pub trait Runner {
+    /// Add a machine, returning its index among the results of `run`.
+    fn add<O: Output + Send + 'static>(
+        &mut self,
+        state: State,
+        input: Receiver<i64>,
+        output: O,
+        mode: InputMode,
+    ) -> usize;
+
+    fn shutdown_handle(&self) -> Shutdown;
+
+    /// Run all machines until every one of them halted or stopped.
+    fn run(self) -> Result<Vec<Finished>>;
+}
+
+/// What happened during a slice of execution.
+enum Slice {
+    /// The machine used up its quantum, or is waiting for input.
+    Yielded,
+    Exited(Exit),
+}
+
+struct Machine {
+    state: State,
+    input: Receiver<i64>,
+    output: Option<Box<dyn Output + Send>>,
+    mode: InputMode,
+}
+
+impl Machine {
+    fn new<O: Output + Send + 'static>(
+        state: State,
+        input: Receiver<i64>,
+        output: O,
+        mode: InputMode,
+    ) -> Self {
+        Machine {
+            state,
+            input,
+            output: Some(Box::new(output)),
+            mode,
+        }
+    }
+
+    /// Receive the next input, waiting for up to `timeout`. Returns `Err(true)` if the channel
+    /// is closed, and `Err(false)` if it is empty.
+    fn receive(&self, timeout: Option<Duration>) -> std::result::Result<i64, bool> {
+        match timeout {
+            Some(t) => self
+                .input
+                .recv_timeout(t)
+                .map_err(|e| e == RecvTimeoutError::Disconnected),
+            None => self
+                .input
+                .try_recv()
+                .map_err(|e| e == TryRecvError::Disconnected),
+        }
+    }
+
+    /// Execute up to `QUANTUM` instructions. Blocking machines wait for up to `timeout` when
+    /// input is needed.
+    fn run_slice(&mut self, id: usize, timeout: Option<Duration>) -> Result<Slice> {
+        let timeout = match self.mode {
+            InputMode::Blocking => timeout,
+            InputMode::NonBlocking { .. } => None,
+        };
+        let mut outputs = Vec::new();
+
+        for _ in 0..QUANTUM {
+            match self.state.step(&mut outputs)? {
+                None | Some(IntCodeResult::Watch(_)) | Some(IntCodeResult::BudgetExhausted) => {}
+                Some(IntCodeResult::Output) => {
+                    if let Some(output) = self.output.as_mut() {
+                        for v in outputs.drain(..) {
+                            output.output(v)?;
+                        }
+                    }
+                }
+                Some(IntCodeResult::Input) => {
+                    match (self.receive(timeout), self.mode) {
+                        (Ok(v), _) => self.state.inputs.push_back(v),
+                        (Err(true), _) => return Ok(Slice::Exited(Exit::Closed)),
+                        (Err(false), InputMode::Blocking) => return Ok(Slice::Yielded),
+                        (Err(false), InputMode::NonBlocking { default }) => {
+                            // give the other machines a chance to send something
+                            self.state.inputs.push_back(default);
+                            return Ok(Slice::Yielded);
+                        }
+                    }
+                }
+                Some(IntCodeResult::Halt) => return Ok(Slice::Exited(Exit::Halted)),
+                Some(IntCodeResult::InfiniteLoop) => {
+                    return Err(format_err!("Machine {} is stuck in an infinite loop", id))
+                }
+            }
+        }
+
+        Ok(Slice::Yielded)
+    }
+
+    fn finish(self, exit: Exit) -> Finished {
+        Finished {
+            state: self.state,
+            exit,
+        }
+    }
+}
+
+/// Runs every machine on its own thread.
+#[derive(Default)]
+pub struct Threaded {
+    machines: Vec<Machine>,
+    shutdown: Shutdown,
+}
+
+impl Threaded {
+    pub fn new() -> Self {
+        Threaded::default()
+    }
+}
+
+impl Runner for Threaded {
+    fn add<O: Output + Send + 'static>(
+        &mut self,
+        state: State,
+        input: Receiver<i64>,
+        output: O,
+        mode: InputMode,
+    ) -> usize {
+        self.machines.push(Machine::new(state, input, output, mode));
+        self.machines.len() - 1
+    }
+
+    fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Run all machines until every one of them halted or stopped. Blocks forever if machines
+    /// wait for each other's input, unless they are stopped through a `Shutdown` handle.
+    fn run(self) -> Result<Vec<Finished>> {
+        let Threaded { machines, shutdown } = self;
+
+        let handles: Vec<_> = machines
+            .into_iter()
+            .enumerate()
+            .map(|(id, mut machine)| {
+                let shutdown = shutdown.clone();
+                std::thread::spawn(move || -> Result<Finished> {
+                    loop {
+                        if shutdown.is_stopped() {
+                            return Ok(machine.finish(Exit::Stopped));
+                        }
+
+                        let res = machine.run_slice(id, Some(POLL_INTERVAL));
+                        match res {
+                            Ok(Slice::Yielded) => {}
+                            Ok(Slice::Exited(exit)) => return Ok(machine.finish(exit)),
+                            Err(e) => {
+                                shutdown.stop();
+                                return Err(e);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let mut out = Vec::new();
+        for handle in handles {
+            let res = handle
+                .join()
+                .map_err(|_| format_err!("Machine thread panicked"))?;
+            out.push(res?);
+        }
+
+        Ok(out)
+    }
+}
+
+/// Runs all machines on the calling thread, switching between them in a fixed order whenever
+/// the current machine waits for input or has used up its quantum.
+#[derive(Default)]
+pub struct Deterministic {
+    machines: Vec<Machine>,
+    shutdown: Shutdown,
+}
+
+impl Deterministic {
+    pub fn new() -> Self {
+        Deterministic::default()
+    }
+}
+
+impl Runner for Deterministic {
+    fn add<O: Output + Send + 'static>(
+        &mut self,
+        state: State,
+        input: Receiver<i64>,
+        output: O,
+        mode: InputMode,
+    ) -> usize {
+        self.machines.push(Machine::new(state, input, output, mode));
+        self.machines.len() - 1
+    }
+
+    fn shutdown_handle(&self) -> Shutdown {
+        self.shutdown.clone()
+    }
+
+    /// Run all machines until every one of them halted or stopped. Fails if all remaining
+    /// machines wait for input that no machine will send.
+    fn run(mut self) -> Result<Vec<Finished>> {
+        let mut exits: Vec<Option<Exit>> = vec![None; self.machines.len()];
+
+        while exits.iter().any(|e| e.is_none()) {
+            let mut progress = false;
+
+            for (id, machine) in self.machines.iter_mut().enumerate() {
+                if exits[id].is_some() {
+                    continue;
+                }
+
+                if self.shutdown.is_stopped() {
+                    exits[id] = Some(Exit::Stopped);
+                    continue;
+                }
+
+                let steps = machine.state.steps;
+                if let Slice::Exited(exit) = machine.run_slice(id, None)? {
+                    exits[id] = Some(exit);
+                    machine.output = None;
+                    progress = true;
+                }
+                progress |= machine.state.steps != steps;
+            }
+
+            if !progress && !self.shutdown.is_stopped() {
+                return Err(format_err!("All machines are waiting for input"));
+            }
+        }
+
+        Ok(self
+            .machines
+            .into_iter()
+            .zip(exits)
+            .map(|(m, e)| m.finish(e.unwrap_or(Exit::Stopped)))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::io::FnOutput;
+    use crate::intcode::parse_program;
+    use crate::util::read_to_string;
+    use std::sync::mpsc::{channel, Sender};
+
+    /// Run the day 7 amplifiers in a feedback loop, returning the last signal.
+    fn feedback_loop<R: Runner>(mut runner: R, phases: &[i64]) -> i64 {
+        let program = parse_program(&read_to_string("data/day07/input").unwrap()).unwrap();
+
+        let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+            phases.iter().map(|_| channel()).unzip();
+        for (tx, &phase) in senders.iter().zip(phases) {
+            tx.send(phase).unwrap();
+        }
+        senders[0].send(0).unwrap();
+
+        let (signal_tx, signal_rx) = channel();
+        for (i, rx) in receivers.into_iter().enumerate() {
+            let mut next = senders[(i + 1) % phases.len()].clone();
+            let state = State::new(program.clone());
+
+            if i + 1 == phases.len() {
+                let mut signal_tx = signal_tx.clone();
+                let output = FnOutput(move |v| {
+                    next.output(v)?;
+                    signal_tx.output(v)
+                });
+                runner.add(state, rx, output, InputMode::Blocking);
+            } else {
+                runner.add(state, rx, next, InputMode::Blocking);
+            }
+        }
+        drop(senders);
+        drop(signal_tx);
+
+        let finished = runner.run().unwrap();
+        assert!(finished.iter().all(|f| f.exit == Exit::Halted));
+
+        signal_rx.iter().last().unwrap()
+    }
+
+    #[test]
+    fn test_feedback_loop() {
+        let phases = [9, 7, 8, 5, 6];
+        let threaded = feedback_loop(Threaded::new(), &phases);
+        let deterministic = feedback_loop(Deterministic::new(), &phases);
+        assert_eq!(threaded, deterministic);
+        assert!(deterministic > 0);
+    }
+
+    /// Run three machines in a ring connected by plain channels, each of which passes on its
+    /// input incremented by one, and halts once that exceeds 9. The last two machines still send
+    /// to machines that have halted.
+    fn sender_ring<R: Runner>(mut runner: R) -> Vec<Exit> {
+        let program = assemble(
+            "
+            loop:   IN [v]
+                    ADD [v], #1, [v]
+                    OUT [v]
+                    LT [v], #10, [t]
+                    JT [t], #loop
+                    HLT
+            v:      data 0
+            t:      data 0
+            ",
+        )
+        .unwrap();
+
+        let (senders, receivers): (Vec<Sender<i64>>, Vec<Receiver<i64>>) =
+            (0..3).map(|_| channel()).unzip();
+        senders[0].send(0).unwrap();
+
+        for (i, rx) in receivers.into_iter().enumerate() {
+            let next = senders[(i + 1) % 3].clone();
+            runner.add(State::new(program.clone()), rx, next, InputMode::Blocking);
+        }
+        drop(senders);
+
+        runner.run().unwrap().iter().map(|f| f.exit).collect()
+    }
+
+    #[test]
+    fn test_sender_ring() {
+        assert_eq!(vec![Exit::Halted; 3], sender_ring(Threaded::new()));
+        assert_eq!(vec![Exit::Halted; 3], sender_ring(Deterministic::new()));
+    }
+
+    /// A machine that adds up its inputs and outputs the sum after every -1.
+    fn adder() -> State {
+        State::new(
+            assemble(
+                "
+                loop:   IN [v]
+                        EQ [v], #-1, [t]
+                        JT [t], #idle
+                        ADD [sum], [v], [sum]
+                        JT #1, #loop
+                idle:   OUT [sum]
+                        JT #1, #loop
+                v:      data 0
+                t:      data 0
+                sum:    data 0
+                ",
+            )
+            .unwrap(),
+        )
+    }
+
+    fn idle_outputs<R: Runner>(mut runner: R) -> (Vec<i64>, Vec<Exit>) {
+        let (tx, rx) = channel();
+        for v in 1..=4 {
+            tx.send(v).unwrap();
+        }
+
+        let shutdown = runner.shutdown_handle();
+        let (out_tx, out_rx) = channel();
+        let output = FnOutput(move |v| {
+            if v == 10 {
+                shutdown.stop();
+            }
+            out_tx.send(v).map_err(|_| format_err!("closed"))
+        });
+        runner.add(adder(), rx, output, InputMode::NonBlocking { default: -1 });
+
+        let finished = runner.run().unwrap();
+        drop(tx);
+
+        let outputs: Vec<i64> = out_rx.iter().collect();
+        (outputs, finished.iter().map(|f| f.exit).collect())
+    }
+
+    #[test]
+    fn test_non_blocking_and_shutdown() {
+        let (outputs, exits) = idle_outputs(Deterministic::new());
+        assert_eq!(vec![10], outputs);
+        assert_eq!(vec![Exit::Stopped], exits);
+
+        let (outputs, exits) = idle_outputs(Threaded::new());
+        assert_eq!(Some(&10), outputs.last());
+        assert_eq!(vec![Exit::Stopped], exits);
+    }
+
+    #[test]
+    fn test_closed_and_deadlock() {
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(-1).unwrap();
+        drop(tx);
+
+        let mut runner = Deterministic::new();
+        let (out_tx, out_rx) = channel();
+        runner.add(adder(), rx, out_tx, InputMode::Blocking);
+        let finished = runner.run().unwrap();
+        assert_eq!(Exit::Closed, finished[0].exit);
+        assert_eq!(vec![1], out_rx.iter().collect::<Vec<_>>());
+
+        let (_tx, rx) = channel();
+        let mut runner = Deterministic::new();
+        runner.add(adder(), rx, Vec::new(), InputMode::Blocking);
+        assert!(runner.run().is_err());
+    }
+}
@@ -0,0 +1,166 @@
+//! Saving and restoring the state of an Intcode machine.
+//!
+//! Snapshots are stored as text after a `intcode-snapshot <version>` header, with one
+//! `<key> <value>` line each for `ic`, `relative_base` and `inputs`, and a
+//! `memory <address> <values>` line for each allocated region of memory, so that sparse memory
+//! stays sparse. Lists are comma-separated, empty lists are written as `-`.
+//!
+//! Only the machine state is saved; watchpoints and traces are debugging aids and are not part of
+//! a snapshot.
+
+use super::memory::Memory;
+use super::trace::{parse_list, parse_num, write_list};
+use super::word::Word;
+use super::State;
+use crate::result::{format_err, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+const HEADER: &str = "intcode-snapshot";
+const VERSION: u32 = 1;
+
+impl<M: Memory> State<M> {
+    pub fn write_snapshot<W: Write>(&self, w: &mut W) -> Result<()> {
+        writeln!(w, "{} {}", HEADER, VERSION)?;
+        writeln!(w, "ic {}", self.ic)?;
+        writeln!(w, "relative_base {}", self.relative_base)?;
+        write!(w, "inputs")?;
+        write_list(w, &self.inputs.iter().collect::<Vec<_>>())?;
+        writeln!(w)?;
+        for (start, words) in self.memory.regions() {
+            write!(w, "memory {}", start)?;
+            write_list(w, words)?;
+            writeln!(w)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn read_snapshot<R: BufRead>(r: R) -> Result<Self> {
+        let mut lines = r.lines();
+
+        let header = lines
+            .next()
+            .ok_or_else(|| format_err!("Empty snapshot file"))??;
+        let version: u32 = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [HEADER, v] => parse_num(v)?,
+            _ => return Err(format_err!("Not a snapshot file: {}", header)),
+        };
+        if version != VERSION {
+            return Err(format_err!("Unsupported snapshot version: {}", version));
+        }
+
+        let mut ic = None;
+        let mut relative_base = None;
+        let mut inputs = None;
+        let mut memory = M::from_program(Vec::new());
+
+        for line in lines {
+            let line = line?;
+            let mut words = line.split_whitespace();
+
+            let key = match words.next() {
+                Some(k) => k,
+                None => continue,
+            };
+            let value = words
+                .next()
+                .ok_or_else(|| format_err!("Missing value for '{}'", key))?;
+
+            match key {
+                "ic" => ic = Some(parse_num(value)?),
+                "relative_base" => relative_base = Some(parse_num(value)?),
+                "inputs" => inputs = Some(parse_list(value, M::Word::parse)?),
+                "memory" => {
+                    let start: usize = parse_num(value)?;
+                    let words = words
+                        .next()
+                        .ok_or_else(|| format_err!("Missing words of memory at {}", start))?;
+                    for (i, v) in parse_list(words, M::Word::parse)?.into_iter().enumerate() {
+                        memory.write(start + i, v);
+                    }
+                }
+                _ => return Err(format_err!("Unknown snapshot entry: {}", key)),
+            }
+        }
+
+        let missing = |k| format_err!("Snapshot is missing '{}'", k);
+
+        let mut state = State::with_memory(memory);
+        state.ic = ic.ok_or_else(|| missing("ic"))?;
+        state.relative_base = relative_base.ok_or_else(|| missing("relative_base"))?;
+        state.inputs = inputs.ok_or_else(|| missing("inputs"))?.into();
+
+        Ok(state)
+    }
+
+    pub fn save_snapshot(&self, path: &str) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write_snapshot(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot(path: &str) -> Result<Self> {
+        State::read_snapshot(BufReader::new(File::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::memory::{PagedMemory, PAGE_SIZE};
+    use crate::intcode::IntCodeResult;
+
+    fn read(buf: &[u8]) -> Result<State> {
+        State::read_snapshot(buf)
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let mut state = State::new(vec![109, 5, 203, 0, 99]);
+        let mut outputs = Vec::new();
+        state.run(&mut outputs).unwrap();
+        state.inputs = vec![7, -1].into();
+
+        let mut buf = Vec::new();
+        state.write_snapshot(&mut buf).unwrap();
+
+        let mut restored = read(&buf).unwrap();
+        assert_eq!(state.memory, restored.memory);
+        assert_eq!(2, restored.ic);
+        assert_eq!(5, restored.relative_base);
+        assert_eq!(restored.inputs, vec![7, -1]);
+
+        assert_eq!(IntCodeResult::Halt, restored.run(&mut outputs).unwrap());
+        assert_eq!(7, restored.memory[5]);
+    }
+
+    #[test]
+    fn test_invalid_snapshots() {
+        assert!(read(&b"intcode-snapshot 2\n"[..]).is_err());
+        assert!(read(&b"intcode-snapshot 1\nic 0\n"[..]).is_err());
+        assert!(read(&b"intcode-snapshot 1\nic 0\nrelative_base 0\nmemory 5\n"[..]).is_err());
+
+        let empty = b"intcode-snapshot 1\nic 2\nrelative_base 0\ninputs -\n";
+        assert!(read(&empty[..]).unwrap().memory.is_empty());
+    }
+
+    #[test]
+    fn test_sparse_snapshot() {
+        let mut state: State<PagedMemory> =
+            State::with_memory(PagedMemory::from_program(vec![1, 2, 3]));
+        state.memory.write(1 << 40, 42);
+
+        let mut buf = Vec::new();
+        state.write_snapshot(&mut buf).unwrap();
+        assert!(buf.len() < 3 * PAGE_SIZE);
+
+        let restored: State<PagedMemory> = State::read_snapshot(&buf[..]).unwrap();
+        assert_eq!(42, restored.memory.read(1 << 40));
+        assert_eq!(2, restored.memory.read(1));
+        assert_eq!((1 << 40) + 1, restored.memory.len());
+        assert_eq!(2, restored.memory.n_pages());
+    }
+}
@@ -0,0 +1,324 @@
+//! Networks of Intcode machines with a fixed topology, such as the amplifiers of day 7.
+//!
+//! Machines are connected by directed edges. Every output of a machine is sent along all of its
+//! outgoing edges, values arriving on several edges are read in the order they were sent, and
+//! edges that leave the topology only collect values. Each machine can be seeded with initial
+//! inputs, which it reads before anything sent to it.
+
+use super::io::Output;
+use super::runner::{Deterministic, InputMode, Runner};
+use super::State;
+use crate::result::{format_err, Result};
+use permutohedron::LexicalPermutation;
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Machine {
+    pub program: Vec<i64>,
+    /// Initial inputs, e.g. a phase setting.
+    pub inputs: Vec<i64>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Edge {
+    pub from: usize,
+    /// The receiving machine, or `None` for an edge leaving the topology.
+    pub to: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Topology {
+    pub machines: Vec<Machine>,
+    pub edges: Vec<Edge>,
+}
+
+/// Sends the outputs of a machine along its edges, and records them.
+struct Fanout {
+    edges: Vec<(usize, Option<Sender<i64>>)>,
+    log: Arc<Mutex<Vec<Vec<i64>>>>,
+}
+
+impl Output for Fanout {
+    fn output(&mut self, value: i64) -> Result<()> {
+        let mut log = self
+            .log
+            .lock()
+            .map_err(|_| format_err!("Output log is poisoned"))?;
+
+        for (edge, tx) in &self.edges {
+            log[*edge].push(value);
+            if let Some(tx) = tx {
+                // the receiving machine may have halted already
+                let _ = tx.send(value);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Topology {
+    pub fn new() -> Self {
+        Topology::default()
+    }
+
+    /// `n` machines running `program` connected in a chain, the first one of which reads
+    /// `input`. Returns the topology and the edge leaving the last machine.
+    pub fn chain(program: &[i64], n: usize, input: i64) -> Result<(Self, usize)> {
+        if n == 0 {
+            return Err(format_err!("A chain needs at least one machine"));
+        }
+
+        let mut topology = Topology::new();
+        for i in 0..n {
+            let inputs = if i == 0 { vec![input] } else { Vec::new() };
+            topology.add_machine(program.to_vec(), inputs);
+            if i > 0 {
+                topology.connect(i - 1, i);
+            }
+        }
+
+        let out = topology.tap(n - 1);
+        Ok((topology, out))
+    }
+
+    /// `n` machines running `program` connected in a ring, the first one of which reads
+    /// `input`. Returns the topology and the edge from the last machine back to the first one.
+    pub fn ring(program: &[i64], n: usize, input: i64) -> Result<(Self, usize)> {
+        if n == 0 {
+            return Err(format_err!("A ring needs at least one machine"));
+        }
+
+        let (mut topology, _) = Topology::chain(program, n, input)?;
+        topology.edges.pop();
+
+        let out = topology.connect(n - 1, 0);
+        Ok((topology, out))
+    }
+
+    /// Add a machine, returning its index.
+    pub fn add_machine(&mut self, program: Vec<i64>, inputs: Vec<i64>) -> usize {
+        self.machines.push(Machine { program, inputs });
+        self.machines.len() - 1
+    }
+
+    /// Send the outputs of machine `from` to machine `to`, returning the index of the edge.
+    pub fn connect(&mut self, from: usize, to: usize) -> usize {
+        self.edges.push(Edge { from, to: Some(to) });
+        self.edges.len() - 1
+    }
+
+    /// Collect the outputs of machine `from`, returning the index of the edge.
+    pub fn tap(&mut self, from: usize) -> usize {
+        self.edges.push(Edge { from, to: None });
+        self.edges.len() - 1
+    }
+
+    /// Run all machines to completion on a deterministic scheduler, returning the values sent
+    /// along each edge.
+    pub fn run(&self) -> Result<Vec<Vec<i64>>> {
+        self.run_on(Deterministic::new())
+    }
+
+    /// Check that all edges connect machines of the topology.
+    fn validate(&self) -> Result<()> {
+        let n = self.machines.len();
+        for (e, edge) in self.edges.iter().enumerate() {
+            for &machine in std::iter::once(&edge.from).chain(&edge.to) {
+                if machine >= n {
+                    return Err(format_err!(
+                        "Edge {} refers to machine {}, but there are only {} machines",
+                        e,
+                        machine,
+                        n
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run all machines to completion on `runner`, returning the values sent along each edge.
+    pub fn run_on<R: Runner>(&self, mut runner: R) -> Result<Vec<Vec<i64>>> {
+        self.validate()?;
+
+        let log = Arc::new(Mutex::new(vec![Vec::new(); self.edges.len()]));
+
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            self.machines.iter().map(|_| channel()).unzip();
+        for (machine, tx) in self.machines.iter().zip(&senders) {
+            for &v in &machine.inputs {
+                tx.send(v)?;
+            }
+        }
+
+        for (i, (machine, rx)) in self.machines.iter().zip(receivers).enumerate() {
+            let mut edges = Vec::new();
+            for (e, edge) in self.edges.iter().enumerate() {
+                if edge.from == i {
+                    edges.push((e, edge.to.map(|to| senders[to].clone())));
+                }
+            }
+
+            let fanout = Fanout {
+                edges,
+                log: log.clone(),
+            };
+            let state = State::new(machine.program.clone());
+            runner.add(state, rx, fanout, InputMode::Blocking);
+        }
+
+        // machines without incoming edges stop once they have read their initial inputs
+        drop(senders);
+        runner.run()?;
+
+        let log = log
+            .lock()
+            .map_err(|_| format_err!("Output log is poisoned"))?;
+        Ok(log.clone())
+    }
+
+    /// A copy of the topology in which every machine reads the corresponding phase setting
+    /// before its initial inputs.
+    pub fn with_phases(&self, phases: &[i64]) -> Result<Self> {
+        if phases.len() != self.machines.len() {
+            return Err(format_err!(
+                "{} phases for {} machines",
+                phases.len(),
+                self.machines.len()
+            ));
+        }
+
+        let mut topology = self.clone();
+        for (machine, &phase) in topology.machines.iter_mut().zip(phases) {
+            machine.inputs.insert(0, phase);
+        }
+        Ok(topology)
+    }
+
+    /// Try all assignments of `phases` to the machines. Returns the phases that maximize the
+    /// last value sent along `edge`, and that value.
+    pub fn best_phases(&self, phases: &[i64], edge: usize) -> Result<Option<(Vec<i64>, i64)>> {
+        self.validate()?;
+        if edge >= self.edges.len() {
+            return Err(format_err!(
+                "No edge {}, there are only {}",
+                edge,
+                self.edges.len()
+            ));
+        }
+
+        let mut permutation = phases.to_vec();
+        permutation.sort();
+
+        let mut best: Option<(Vec<i64>, i64)> = None;
+        loop {
+            let outputs = self.with_phases(&permutation)?.run()?;
+            if let Some(&signal) = outputs[edge].last() {
+                if best.as_ref().map(|(_, s)| signal > *s).unwrap_or(true) {
+                    best = Some((permutation.clone(), signal));
+                }
+            }
+
+            if !permutation.next_permutation() {
+                break;
+            }
+        }
+
+        Ok(best)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::runner::Threaded;
+
+    #[test]
+    fn test_examples() {
+        let program = vec![
+            3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+        ];
+        let (chain, out) = Topology::chain(&program, 5, 0).unwrap();
+        assert_eq!(
+            vec![vec![4], vec![43], vec![432], vec![4321], vec![43210]],
+            chain.with_phases(&[4, 3, 2, 1, 0]).unwrap().run().unwrap()
+        );
+        assert_eq!(
+            Some((vec![4, 3, 2, 1, 0], 43210)),
+            chain.best_phases(&[0, 1, 2, 3, 4], out).unwrap()
+        );
+        assert!(chain.with_phases(&[0, 1]).is_err());
+
+        let program = vec![
+            3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1,
+            28, 1005, 28, 6, 99, 0, 0, 5,
+        ];
+        let (ring, out) = Topology::ring(&program, 5, 0).unwrap();
+        let ring = ring.with_phases(&[9, 8, 7, 6, 5]).unwrap();
+        let outputs = ring.run().unwrap();
+        assert_eq!(Some(&139_629_729), outputs[out].last());
+        assert_eq!(outputs, ring.run_on(Threaded::new()).unwrap());
+    }
+
+    #[test]
+    fn test_empty() {
+        assert!(Topology::chain(&[99], 0, 0).is_err());
+        assert!(Topology::ring(&[99], 0, 0).is_err());
+
+        let (ring, out) = Topology::ring(&[3, 0, 4, 0, 99], 1, 7).unwrap();
+        assert_eq!(vec![7], ring.run().unwrap()[out]);
+    }
+
+    #[test]
+    fn test_invalid_indices() {
+        let (mut chain, out) = Topology::chain(&[3, 0, 4, 0, 99], 2, 7).unwrap();
+        assert!(chain.best_phases(&[0, 1], out + 1).is_err());
+
+        chain.connect(1, 2);
+        assert!(chain.run().is_err());
+        assert!(chain.run_on(Threaded::new()).is_err());
+        assert!(chain.best_phases(&[0, 1], out).is_err());
+
+        chain.edges.pop();
+        chain.tap(5);
+        assert!(chain.run().is_err());
+    }
+
+    #[test]
+    fn test_fan_out_and_in() {
+        // two doublers fed by one source, summed by a fourth machine
+        let source = assemble("OUT #5\nOUT #7\nHLT").unwrap();
+        let double = assemble("IN [v]\nMUL [v], #2, [v]\nOUT [v]\nJT #1, #0\nv: data 0").unwrap();
+        let sum = assemble(
+            "
+            loop:   IN [v]
+                    ADD [v], [s], [s]
+                    OUT [s]
+                    JT #1, #loop
+            v:      data 0
+            s:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut topology = Topology::new();
+        let src = topology.add_machine(source, Vec::new());
+        let a = topology.add_machine(double.clone(), Vec::new());
+        let b = topology.add_machine(double, Vec::new());
+        let total = topology.add_machine(sum, vec![100]);
+        topology.connect(src, a);
+        topology.connect(src, b);
+        topology.connect(a, total);
+        topology.connect(b, total);
+        let out = topology.tap(total);
+
+        let outputs = topology.run().unwrap();
+        assert_eq!(vec![10, 14], outputs[2]);
+        assert_eq!(Some(&148), outputs[out].last());
+        assert_eq!(5, outputs[out].len());
+    }
+}
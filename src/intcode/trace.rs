@@ -0,0 +1,552 @@
+//! Recording of Intcode executions and replaying them against a recorded input log.
+//!
+//! Traces are stored as text, one event per line, after a `intcode-trace <version> <steps>`
+//! header giving the number of executed instructions:
+//!
+//! ```text
+//! S <ic> <word> <operands> <values> <writes>
+//! I <value>
+//! O <value>
+//! P <address> <value>
+//! ```
+//!
+//! where `<operands>` and `<values>` are comma-separated lists of the raw operand words and the
+//! values the instruction read, `<writes>` is a comma-separated list of `address=value` pairs and
+//! empty lists are written as `-`. `P` events are writes from outside the program, see
+//! `State::poke`.
+
+use super::disasm::Opcode;
+use super::error::IntCodeError;
+use super::memory::Memory;
+use super::word::Word;
+use super::{IntCodeResult, State};
+use crate::result::{format_err, Result};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+
+const HEADER: &str = "intcode-trace";
+const VERSION: u32 = 1;
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Event<W: Word = i64> {
+    /// An executed instruction.
+    Step {
+        ic: usize,
+        word: W,
+        operands: Vec<W>,
+        values: Vec<W>,
+        writes: Vec<(usize, W)>,
+    },
+    Input(W),
+    Output(W),
+    /// A memory cell written from outside the program.
+    Poke {
+        address: usize,
+        value: W,
+    },
+}
+
+pub(super) fn write_list<W: Write, T: std::fmt::Display>(w: &mut W, items: &[T]) -> Result<()> {
+    if items.is_empty() {
+        write!(w, " -")?;
+    } else {
+        let items: Vec<String> = items.iter().map(|i| i.to_string()).collect();
+        write!(w, " {}", items.join(","))?;
+    }
+    Ok(())
+}
+
+pub(super) fn parse_list<T, F: Fn(&str) -> Result<T>>(s: &str, parse: F) -> Result<Vec<T>> {
+    match s {
+        "-" => Ok(Vec::new()),
+        s => s.split(',').map(parse).collect(),
+    }
+}
+
+pub(super) fn parse_num<T>(s: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    Ok(s.parse()?)
+}
+
+impl<W: Word> std::fmt::Display for Event<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self {
+            Event::Step {
+                ic,
+                word,
+                operands,
+                values,
+                writes,
+            } => write!(
+                f,
+                "{}: {} {:?} read {:?} wrote {:?}",
+                ic, word, operands, values, writes
+            ),
+            Event::Input(v) => write!(f, "input {}", v),
+            Event::Output(v) => write!(f, "output {}", v),
+            Event::Poke { address, value } => write!(f, "poke {}={}", address, value),
+        }
+    }
+}
+
+/// The recorded events of an execution.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Trace<W: Word = i64> {
+    /// Whether instructions are recorded, or only inputs and outputs.
+    pub record_steps: bool,
+    pub events: Vec<Event<W>>,
+    /// Number of instructions executed while recording.
+    pub steps: usize,
+
+    current: Option<Event<W>>,
+    io: Option<Event<W>>,
+}
+
+impl<W: Word> Trace<W> {
+    pub fn new(record_steps: bool) -> Self {
+        Trace {
+            record_steps,
+            events: Vec::new(),
+            steps: 0,
+            current: None,
+            io: None,
+        }
+    }
+
+    /// All inputs consumed during the recorded execution, in order.
+    pub fn inputs(&self) -> Vec<W> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Input(v) => Some(v.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// All outputs produced during the recorded execution, in order.
+    pub fn outputs(&self) -> Vec<W> {
+        self.events
+            .iter()
+            .filter_map(|e| match e {
+                Event::Output(v) => Some(v.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub(super) fn begin(&mut self, ic: usize, word: W, operands: Vec<W>) {
+        self.io = None;
+        if self.record_steps {
+            self.current = Some(Event::Step {
+                ic,
+                word,
+                operands,
+                values: Vec::new(),
+                writes: Vec::new(),
+            });
+        }
+    }
+
+    pub(super) fn record_value(&mut self, value: W) {
+        if let Some(Event::Step { values, .. }) = &mut self.current {
+            values.push(value);
+        }
+    }
+
+    pub(super) fn record_write(&mut self, address: usize, value: W) {
+        if let Some(Event::Step { writes, .. }) = &mut self.current {
+            writes.push((address, value));
+        }
+    }
+
+    pub(super) fn record_input(&mut self, value: W) {
+        self.io = Some(Event::Input(value));
+    }
+
+    pub(super) fn record_output(&mut self, value: W) {
+        self.io = Some(Event::Output(value));
+    }
+
+    pub(super) fn record_poke(&mut self, address: usize, value: W) {
+        self.events.push(Event::Poke { address, value });
+    }
+
+    /// Commit the events of the instruction that just completed.
+    pub(super) fn finish(&mut self) {
+        self.steps += 1;
+        if let Some(step) = self.current.take() {
+            self.events.push(step);
+        }
+        if let Some(io) = self.io.take() {
+            self.events.push(io);
+        }
+    }
+
+    /// Discard the events of an instruction that did not execute.
+    pub(super) fn abort(&mut self) {
+        self.current = None;
+        self.io = None;
+    }
+
+    pub fn write<O: Write>(&self, w: &mut O) -> Result<()> {
+        writeln!(w, "{} {} {}", HEADER, VERSION, self.steps)?;
+
+        for event in &self.events {
+            match event {
+                Event::Step {
+                    ic,
+                    word,
+                    operands,
+                    values,
+                    writes,
+                } => {
+                    write!(w, "S {} {}", ic, word)?;
+                    write_list(w, operands)?;
+                    write_list(w, values)?;
+                    let writes: Vec<String> =
+                        writes.iter().map(|(a, v)| format!("{}={}", a, v)).collect();
+                    write_list(w, &writes)?;
+                    writeln!(w)?;
+                }
+                Event::Input(v) => writeln!(w, "I {}", v)?,
+                Event::Output(v) => writeln!(w, "O {}", v)?,
+                Event::Poke { address, value } => writeln!(w, "P {} {}", address, value)?,
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn read<R: BufRead>(r: R) -> Result<Self> {
+        let mut lines = r.lines();
+
+        let header = lines
+            .next()
+            .ok_or_else(|| format_err!("Empty trace file"))??;
+        let (version, steps): (u32, _) = match header.split_whitespace().collect::<Vec<_>>()[..] {
+            [HEADER, v, steps] => (parse_num(v)?, parse_num(steps)?),
+            _ => return Err(format_err!("Not a trace file: {}", header)),
+        };
+        if version != VERSION {
+            return Err(format_err!("Unsupported trace version: {}", version));
+        }
+
+        let mut trace = Trace::new(false);
+        trace.steps = steps;
+        for line in lines {
+            let line = line?;
+            let mut words = line.split_whitespace();
+
+            let event = match words.next() {
+                Some("S") => {
+                    trace.record_steps = true;
+
+                    let mut num = || {
+                        words
+                            .next()
+                            .ok_or_else(|| format_err!("Truncated trace event: {}", line))
+                    };
+                    let ic = parse_num(num()?)?;
+                    let word = W::parse(num()?)?;
+                    let operands = parse_list(num()?, W::parse)?;
+                    let values = parse_list(num()?, W::parse)?;
+                    let writes = parse_list(num()?, |w| {
+                        let mut parts = w.splitn(2, '=');
+                        let a = parse_num(parts.next().unwrap_or(""))?;
+                        let v = W::parse(parts.next().unwrap_or(""))?;
+                        Ok((a, v))
+                    })?;
+
+                    Event::Step {
+                        ic,
+                        word,
+                        operands,
+                        values,
+                        writes,
+                    }
+                }
+                Some("I") => Event::Input(W::parse(words.next().unwrap_or(""))?),
+                Some("O") => Event::Output(W::parse(words.next().unwrap_or(""))?),
+                Some("P") => Event::Poke {
+                    address: parse_num(words.next().unwrap_or(""))?,
+                    value: W::parse(words.next().unwrap_or(""))?,
+                },
+                None => continue,
+                Some(t) => return Err(format_err!("Invalid trace event type: {}", t)),
+            };
+
+            trace.events.push(event);
+        }
+
+        Ok(trace)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        let mut w = BufWriter::new(File::create(path)?);
+        self.write(&mut w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self> {
+        Trace::read(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Start recording a trace, replacing any trace recorded so far.
+    pub fn start_trace(&mut self, record_steps: bool) {
+        self.trace = Some(Trace::new(record_steps));
+    }
+
+    /// Stop recording and return the recorded trace.
+    pub fn take_trace(&mut self) -> Option<Trace<M::Word>> {
+        self.trace.take()
+    }
+
+    pub(super) fn trace_begin(&mut self) {
+        let memory = &self.memory;
+        let extensions = &self.extensions;
+        if let Some(trace) = self.trace.as_mut() {
+            // an instruction counter past the end of memory is reported when executing
+            let word = memory
+                .fetch(self.ic)
+                .unwrap_or_else(|| M::Word::from_i64(0));
+            let n_params = word
+                .to_i64()
+                .and_then(|w| {
+                    Opcode::from_code(w % 100)
+                        .map(|o| o.n_params())
+                        .or_else(|| extensions.get(&(w % 100)).map(|e| e.params.len()))
+                })
+                .unwrap_or(0);
+
+            let operands = (self.ic + 1..self.ic + 1 + n_params)
+                .map_while(|a| memory.fetch(a))
+                .collect();
+
+            trace.begin(self.ic, word, operands);
+        }
+    }
+}
+
+/// Where a replayed execution first differed from the recording.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Divergence<W: Word = i64> {
+    /// Index of the first differing event.
+    pub index: usize,
+    /// The recorded event, or `None` if the replay produced more events.
+    pub expected: Option<Event<W>>,
+    /// The replayed event, or `None` if the replay stopped early.
+    pub actual: Option<Event<W>>,
+    /// The error the replay stopped with, if it stopped early because of one.
+    pub error: Option<IntCodeError>,
+}
+
+impl<W: Word> std::fmt::Display for Divergence<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        let show = |e: &Option<Event<W>>| match e {
+            Some(e) => e.to_string(),
+            None => "nothing".to_owned(),
+        };
+
+        write!(
+            f,
+            "event {}: expected {}, got {}",
+            self.index,
+            show(&self.expected),
+            show(&self.actual)
+        )?;
+        if let Some(e) = &self.error {
+            write!(f, " ({})", e)?;
+        }
+        Ok(())
+    }
+}
+
+/// Re-execute `program` with the inputs from a recorded trace and compare the events.
+/// Recorded pokes are applied again once the replay reaches them.
+///
+/// If the trace only contains inputs and outputs, only those are compared. The replay stops after
+/// as many instructions as were recorded, or when it fails. Returns the first divergence, or
+/// `None` if the replay matches the recording.
+pub fn replay<W: Word>(program: Vec<W>, recorded: &Trace<W>) -> Result<Option<Divergence<W>>> {
+    let mut state = State::with_memory(program);
+    state.inputs = recorded.inputs().into();
+    state.start_trace(recorded.record_steps);
+
+    let mut outputs = Vec::new();
+    let mut error = None;
+    loop {
+        // stop early if the replay already produced more events than were recorded
+        let n_events = state.trace.as_ref().map(|t| t.events.len()).unwrap_or(0);
+        if n_events > recorded.events.len() {
+            break;
+        }
+        if let Some(Event::Poke { address, value }) = recorded.events.get(n_events) {
+            state.poke(*address, value.clone());
+            continue;
+        }
+        // or executed more instructions, e.g. when looping without I/O
+        if state.steps >= recorded.steps {
+            break;
+        }
+
+        match state.step(&mut outputs) {
+            Ok(Some(IntCodeResult::Halt)) | Ok(Some(IntCodeResult::Input)) => break,
+            Ok(_) => {}
+            Err(e) => {
+                error = Some(e);
+                break;
+            }
+        }
+    }
+
+    let actual = state.take_trace().map(|t| t.events).unwrap_or_default();
+
+    for i in 0..recorded.events.len().max(actual.len()) {
+        let expected = recorded.events.get(i);
+        let got = actual.get(i);
+
+        if expected != got {
+            return Ok(Some(Divergence {
+                index: i,
+                expected: expected.cloned(),
+                actual: got.cloned(),
+                error: if got.is_none() { error } else { None },
+            }));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::error::ErrorKind;
+
+    fn echo_double() -> Vec<i64> {
+        assemble(
+            "
+            loop:   IN [x]
+                    JF [x], #end
+                    MUL [x], #2, [x]
+                    OUT [x]
+                    JT #1, #loop
+            end:    HLT
+            x:      data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    fn record(program: Vec<i64>, inputs: Vec<i64>, record_steps: bool) -> Trace {
+        let mut state = State::new(program);
+        state.inputs = inputs.into();
+        state.start_trace(record_steps);
+
+        let mut outputs = Vec::new();
+        while state.run(&mut outputs).unwrap() != IntCodeResult::Halt {}
+
+        state.take_trace().unwrap()
+    }
+
+    #[test]
+    fn test_record_and_roundtrip() {
+        let trace = record(echo_double(), vec![3, 0], true);
+
+        assert_eq!(vec![3, 0], trace.inputs());
+        assert_eq!(vec![6], trace.outputs());
+        assert_eq!(
+            Event::Step {
+                ic: 0,
+                word: 3,
+                operands: vec![15],
+                values: vec![],
+                writes: vec![(15, 3)],
+            },
+            trace.events[0]
+        );
+        assert_eq!(Event::Input(3), trace.events[1]);
+
+        let mut buf = Vec::new();
+        trace.write(&mut buf).unwrap();
+        assert_eq!(trace, Trace::read(&buf[..]).unwrap());
+    }
+
+    #[test]
+    fn test_replay() {
+        let trace = record(echo_double(), vec![3, 4, 0], true);
+        assert_eq!(None, replay(echo_double(), &trace).unwrap());
+
+        // change the multiplier from 2 to 3
+        let mut modified = echo_double();
+        modified[7] = 3;
+
+        let io_trace = record(echo_double(), vec![3, 4, 0], false);
+        let divergence = replay(modified, &io_trace).unwrap().unwrap();
+        assert_eq!(1, divergence.index);
+        assert_eq!(Some(Event::Output(6)), divergence.expected);
+        assert_eq!(Some(Event::Output(9)), divergence.actual);
+    }
+
+    #[test]
+    fn test_pokes() {
+        let mut state = State::new(echo_double());
+        state.inputs = vec![3, 0].into();
+        state.start_trace(true);
+
+        // change the multiplier from 2 to 3 before running
+        state.poke(7, 3);
+        let mut outputs = Vec::new();
+        while state.run(&mut outputs).unwrap() != IntCodeResult::Halt {}
+        assert_eq!(vec![9], outputs);
+
+        let trace = state.take_trace().unwrap();
+        assert_eq!(
+            Event::Poke {
+                address: 7,
+                value: 3
+            },
+            trace.events[0]
+        );
+
+        let mut buf = Vec::new();
+        trace.write(&mut buf).unwrap();
+        assert_eq!(trace, Trace::read(&buf[..]).unwrap());
+
+        assert_eq!(None, replay(echo_double(), &trace).unwrap());
+    }
+
+    #[test]
+    fn test_replay_failures() {
+        let trace = record(echo_double(), vec![3, 0], false);
+        assert_eq!(8, trace.steps);
+
+        // jumping past the end of memory instead of back to the start fails after the output
+        let mut modified = echo_double();
+        modified[13] = 1000;
+        let divergence = replay(modified, &trace).unwrap().unwrap();
+        assert_eq!(2, divergence.index);
+        assert_eq!(Some(Event::Input(0)), divergence.expected);
+        assert_eq!(None, divergence.actual);
+        assert_eq!(
+            ErrorKind::JumpOutOfRange(1000),
+            divergence.error.unwrap().kind
+        );
+
+        // looping without I/O stops after the recorded number of instructions
+        let looping = assemble("loop: JT #1, #loop").unwrap();
+        let divergence = replay(looping, &trace).unwrap().unwrap();
+        assert_eq!(0, divergence.index);
+        assert_eq!(Some(Event::Input(3)), divergence.expected);
+        assert_eq!(None, divergence.error);
+    }
+}
@@ -0,0 +1,113 @@
+//! Watchpoints on Intcode memory.
+//!
+//! A watchpoint covers a range of addresses and triggers on reads, writes or both. Accesses are
+//! checked as instructions execute, and each hit is reported as an `IntCodeResult::Watch` after
+//! the accessing instruction has completed, after which the machine can be resumed.
+
+use super::word::Word;
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/// A range of memory addresses to watch for reads, writes or both.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Watchpoint {
+    pub addresses: Range<usize>,
+    pub on_read: bool,
+    pub on_write: bool,
+}
+
+impl Watchpoint {
+    pub fn matches(&self, kind: AccessKind, address: usize) -> bool {
+        let wanted = match kind {
+            AccessKind::Read => self.on_read,
+            AccessKind::Write => self.on_write,
+        };
+
+        wanted && self.addresses.contains(&address)
+    }
+}
+
+/// A memory access that triggered a watchpoint.
+///
+/// For reads, `old_value` and `new_value` are the same.
+#[derive(Debug, PartialEq, Eq, Hash, Clone)]
+pub struct MemoryAccess<W: Word = i64> {
+    pub kind: AccessKind,
+    /// Address of the instruction performing the access.
+    pub ic: usize,
+    pub address: usize,
+    pub old_value: W,
+    pub new_value: W,
+}
+
+impl<W: Word> std::fmt::Display for MemoryAccess<W> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::result::Result<(), std::fmt::Error> {
+        match self.kind {
+            AccessKind::Read => write!(
+                f,
+                "{}: read [{}] = {}",
+                self.ic, self.address, self.old_value
+            ),
+            AccessKind::Write => write!(
+                f,
+                "{}: write [{}] = {} (was {})",
+                self.ic, self.address, self.new_value, self.old_value
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::{IntCodeResult, State};
+
+    #[test]
+    fn test_watch_write_and_resume() {
+        let program = assemble(
+            "
+                    ADD [score], #5, [score]
+                    MUL [score], #2, [score]
+                    OUT [score]
+                    HLT
+            score:  data 1
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program);
+        state.watch(11..12, false, true);
+
+        let mut outputs = Vec::new();
+        let mut results = Vec::new();
+        loop {
+            match state.run(&mut outputs).unwrap() {
+                IntCodeResult::Halt => break,
+                res => results.push(res),
+            }
+        }
+
+        let write = |ic, old_value, new_value| {
+            IntCodeResult::Watch(MemoryAccess {
+                kind: AccessKind::Write,
+                ic,
+                address: 11,
+                old_value,
+                new_value,
+            })
+        };
+
+        assert_eq!(
+            vec![write(0, 1, 6), write(4, 6, 12), IntCodeResult::Output],
+            results
+        );
+        assert_eq!(vec![12], outputs);
+    }
+}
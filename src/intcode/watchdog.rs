@@ -0,0 +1,244 @@
+//! Detection of programs that are provably stuck in an infinite loop.
+//!
+//! A machine that returns to an exact earlier state (instruction counter, relative base and
+//! memory) without consuming input or producing output in between will keep repeating the same instructions
+//! forever. Machine states are compared with Brent's cycle detection algorithm, so only a single
+//! earlier state has to be kept. Memory is compared through a hash that is updated on every
+//! write, and on every undone write when stepping back.
+
+use super::memory::Memory;
+use super::word::Word;
+use super::State;
+
+fn cell_hash<W: Word>(address: usize, value: &W) -> u64 {
+    if value.is_zero() {
+        // memory is zero-filled, so growing it does not change the hash
+        return 0;
+    }
+
+    // splitmix64 finalizer
+    let mut z = (address as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ value.bits();
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Clone)]
+pub struct LoopDetector {
+    memory_hash: u64,
+    saved: Option<(usize, i64, u64)>,
+    power: usize,
+    steps: usize,
+}
+
+impl LoopDetector {
+    pub fn new<M: Memory>(memory: &M) -> Self {
+        // unallocated memory is zero and does not contribute
+        let memory_hash = memory
+            .regions()
+            .into_iter()
+            .flat_map(|(start, words)| words.iter().enumerate().map(move |(i, v)| (start + i, v)))
+            .map(|(a, v)| cell_hash(a, v))
+            .fold(0, u64::wrapping_add);
+
+        LoopDetector {
+            memory_hash,
+            saved: None,
+            power: 1,
+            steps: 0,
+        }
+    }
+
+    /// Account for a write to memory. Writes made by the interpreter are tracked
+    /// automatically, but writes made directly to `State::memory` have to be reported here.
+    pub fn record_write<W: Word>(&mut self, address: usize, old_value: &W, new_value: &W) {
+        self.memory_hash = self
+            .memory_hash
+            .wrapping_sub(cell_hash(address, old_value))
+            .wrapping_add(cell_hash(address, new_value));
+    }
+
+    /// Forget earlier states, as the machine consumed input, produced output or was stepped back.
+    pub(super) fn reset(&mut self) {
+        self.saved = None;
+        self.power = 1;
+        self.steps = 0;
+    }
+
+    /// Record the state after executing an instruction, returning whether it was seen before.
+    pub(super) fn check(&mut self, ic: usize, relative_base: i64) -> bool {
+        let current = (ic, relative_base, self.memory_hash);
+        if self.saved == Some(current) {
+            return true;
+        }
+
+        self.steps += 1;
+        if self.saved.is_none() || self.steps == self.power {
+            self.saved = Some(current);
+            self.power *= 2;
+            self.steps = 0;
+        }
+
+        false
+    }
+}
+
+impl<M: Memory> State<M> {
+    /// Report `IntCodeResult::InfiniteLoop` once the machine is provably stuck.
+    pub fn enable_loop_detection(&mut self) {
+        self.loop_detector = Some(LoopDetector::new(&self.memory));
+    }
+
+    pub fn disable_loop_detection(&mut self) {
+        self.loop_detector = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::memory::PagedMemory;
+    use crate::intcode::IntCodeResult;
+
+    #[test]
+    fn test_infinite_loop() {
+        // count up to 100, then wait forever for a flag to be set
+        let program = assemble(
+            "
+            count:  ADD [n], #1, [n]
+                    LT [n], #100, [t]
+                    JT [t], #count
+            wait:   JF [flag], #wait
+                    OUT [n]
+                    HLT
+            n:      data 0
+            t:      data 0
+            flag:   data 0
+            ",
+        )
+        .unwrap();
+        let flag = program.len() - 1;
+
+        let mut state = State::new(program);
+        state.enable_loop_detection();
+
+        let mut outputs = Vec::new();
+        assert_eq!(
+            IntCodeResult::InfiniteLoop,
+            state.run(&mut outputs).unwrap()
+        );
+        assert!(state.steps > 300);
+
+        // the loop is found again while nothing changes
+        let steps = state.steps;
+        assert_eq!(
+            IntCodeResult::InfiniteLoop,
+            state.run(&mut outputs).unwrap()
+        );
+        assert!(state.steps > steps);
+
+        // setting the flag from outside lets the machine carry on
+        state.memory[flag] = 1;
+        state
+            .loop_detector
+            .as_mut()
+            .unwrap()
+            .record_write(flag, &0i64, &1);
+        assert_eq!(IntCodeResult::Output, state.run(&mut outputs).unwrap());
+        assert_eq!(IntCodeResult::Halt, state.run(&mut outputs).unwrap());
+        assert_eq!(vec![100], outputs);
+    }
+
+    #[test]
+    fn test_output_is_not_a_loop() {
+        // toggle a flag forever while printing it
+        let program = assemble(
+            "
+            toggle: EQ [flag], #0, [flag]
+                    OUT [flag]
+                    JT #1, #toggle
+            flag:   data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program);
+        state.enable_loop_detection();
+
+        let mut outputs = Vec::new();
+        for _ in 0..100 {
+            assert_eq!(IntCodeResult::Output, state.run(&mut outputs).unwrap());
+        }
+        assert_eq!((0..100).map(|i| (i + 1) % 2).collect::<Vec<_>>(), outputs);
+    }
+
+    #[test]
+    fn test_input_is_not_a_loop() {
+        // echo inputs forever
+        let program = assemble(
+            "
+            echo:   IN [v]
+                    OUT [v]
+                    JT #1, #echo
+            v:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::new(program);
+        state.enable_loop_detection();
+
+        let mut outputs = Vec::new();
+        for _ in 0..10 {
+            assert_eq!(IntCodeResult::Input, state.run(&mut outputs).unwrap());
+            assert_eq!(IntCodeResult::Input, state.run(&mut outputs).unwrap());
+            state.inputs.push_back(7);
+            assert_eq!(IntCodeResult::Output, state.run(&mut outputs).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_step_back() {
+        let program = assemble(
+            "
+            loop:   ADD [n], #1, [n]
+                    ARB #1
+                    ADD [n], #0, rb+100
+                    JT #1, #loop
+            n:      data 0
+            ",
+        )
+        .unwrap();
+
+        let mut state = State::with_memory(PagedMemory::from_program(program));
+        state.enable_loop_detection();
+        state.enable_history(None);
+        state.run_budget = Some(1000);
+        assert_eq!(
+            IntCodeResult::BudgetExhausted,
+            state.run(&mut Vec::new()).unwrap()
+        );
+
+        // the hash is updated when stepping back, and matches the one of the restored memory
+        state.rewind(500);
+        let hash = state.loop_detector.as_ref().unwrap().memory_hash;
+        assert_eq!(LoopDetector::new(&state.memory).memory_hash, hash);
+    }
+
+    #[test]
+    fn test_run_budget() {
+        let mut state = State::new(vec![1105, 1, 0]);
+        state.run_budget = Some(50);
+
+        let mut outputs = Vec::new();
+        for i in 1..=3 {
+            assert_eq!(
+                IntCodeResult::BudgetExhausted,
+                state.run(&mut outputs).unwrap()
+            );
+            assert_eq!(50 * i, state.steps);
+        }
+    }
+}
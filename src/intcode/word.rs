@@ -0,0 +1,194 @@
+//! Word types an Intcode machine can compute with.
+//!
+//! Arithmetic is checked: an addition or multiplication whose result does not fit into the word
+//! type fails with `ErrorKind::Overflow`. Opcodes, addresses, jump targets and relative base
+//! offsets are always handled as `i64`, and words used as such must fit into one.
+
+use crate::result::Result;
+use num::bigint::BigInt;
+use num::{ToPrimitive, Zero};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{Debug, Display};
+use std::hash::{Hash, Hasher};
+
+pub trait Word: Clone + Eq + Ord + Hash + Debug + Display + Send + 'static {
+    fn from_i64(value: i64) -> Self;
+
+    /// The value as an `i64`, or `None` if it does not fit.
+    fn to_i64(&self) -> Option<i64>;
+
+    /// `self + other`, or `None` on overflow.
+    fn try_add(&self, other: &Self) -> Option<Self>;
+
+    /// `self * other`, or `None` on overflow.
+    fn try_mul(&self, other: &Self) -> Option<Self>;
+
+    fn parse(s: &str) -> Result<Self>;
+
+    fn is_zero(&self) -> bool;
+
+    /// The value mixed into 64 bits, for hashing memory contents.
+    fn bits(&self) -> u64;
+}
+
+impl Word for i64 {
+    fn from_i64(value: i64) -> Self {
+        value
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        Some(*self)
+    }
+
+    fn try_add(&self, other: &Self) -> Option<Self> {
+        self.checked_add(*other)
+    }
+
+    fn try_mul(&self, other: &Self) -> Option<Self> {
+        self.checked_mul(*other)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(s.parse()?)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    fn bits(&self) -> u64 {
+        *self as u64
+    }
+}
+
+impl Word for i128 {
+    fn from_i64(value: i64) -> Self {
+        i128::from(value)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn try_add(&self, other: &Self) -> Option<Self> {
+        self.checked_add(*other)
+    }
+
+    fn try_mul(&self, other: &Self) -> Option<Self> {
+        self.checked_mul(*other)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(s.parse()?)
+    }
+
+    fn is_zero(&self) -> bool {
+        *self == 0
+    }
+
+    fn bits(&self) -> u64 {
+        (*self as u64) ^ ((*self >> 64) as u64)
+    }
+}
+
+/// Arbitrary precision, which never overflows.
+impl Word for BigInt {
+    fn from_i64(value: i64) -> Self {
+        BigInt::from(value)
+    }
+
+    fn to_i64(&self) -> Option<i64> {
+        ToPrimitive::to_i64(self)
+    }
+
+    fn try_add(&self, other: &Self) -> Option<Self> {
+        Some(self + other)
+    }
+
+    fn try_mul(&self, other: &Self) -> Option<Self> {
+        Some(self * other)
+    }
+
+    fn parse(s: &str) -> Result<Self> {
+        Ok(s.parse()?)
+    }
+
+    fn is_zero(&self) -> bool {
+        Zero::is_zero(self)
+    }
+
+    fn bits(&self) -> u64 {
+        match Word::to_i64(self) {
+            Some(v) => v as u64,
+            None => {
+                let mut hasher = DefaultHasher::new();
+                self.hash(&mut hasher);
+                hasher.finish()
+            }
+        }
+    }
+}
+
+/// Convert a program to another word type.
+pub fn convert<W: Word>(program: &[i64]) -> Vec<W> {
+    program.iter().map(|&v| W::from_i64(v)).collect()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+    use crate::intcode::asm::assemble;
+    use crate::intcode::error::ErrorKind;
+    use crate::intcode::{IntCodeResult, State};
+
+    fn run<W: Word>(program: &[i64], input: W) -> std::result::Result<W, ErrorKind> {
+        let mut state = State::with_memory(convert::<W>(program));
+        state.inputs.push_back(input);
+
+        let mut outputs = Vec::new();
+        match state.run(&mut outputs) {
+            Ok(IntCodeResult::Output) => Ok(outputs.remove(0)),
+            Ok(res) => panic!("Unexpected result: {:?}", res),
+            Err(e) => Err(e.kind),
+        }
+    }
+
+    #[test]
+    fn test_overflow() {
+        // square the input four times
+        let program = assemble(
+            "
+                    IN [x]
+                    MUL [x], [x], [x]
+                    MUL [x], [x], [x]
+                    MUL [x], [x], [x]
+                    MUL [x], [x], [x]
+                    OUT [x]
+                    HLT
+            x:      data 0
+            ",
+        )
+        .unwrap();
+
+        assert_eq!(Ok(65536), run(&program, 2_i64));
+        assert_eq!(Err(ErrorKind::Overflow), run(&program, 16_i64));
+        assert_eq!(Ok(1 << 64), run(&program, 16_i128));
+        assert_eq!(Err(ErrorKind::Overflow), run(&program, 256_i128));
+
+        let big = run(&program, BigInt::from(256)).unwrap();
+        assert_eq!(BigInt::from(1) << 128, big);
+        assert_eq!("340282366920938463463374607431768211456", big.to_string());
+    }
+
+    #[test]
+    fn test_large_addresses() {
+        // a word that does not fit into an i64 cannot be used as an address
+        let mut program = convert::<BigInt>(&[1, 0, 0, 0, 99]);
+        program[1] = BigInt::from(1) << 70;
+        let mut state = State::with_memory(program);
+
+        let err = state.run(&mut Vec::new()).unwrap_err();
+        assert_eq!(ErrorKind::WordOutOfRange, err.kind);
+    }
+}